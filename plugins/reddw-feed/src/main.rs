@@ -0,0 +1,440 @@
+#![feature(async_closure, let_chains)]
+#![windows_subsystem = "windows"]
+use anyhow::{anyhow, Result};
+use feed_rs::model::{Entry, Feed};
+use lazy_static::lazy_static;
+use reddw_source_plugin::{ReddwSourceTrait, Wallpaper};
+use regex::Regex;
+use reqwest::{Client, Url};
+use rmp_serde::to_vec;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error};
+
+lazy_static! {
+    static ref IMG_TAG: Regex =
+        Regex::new(r#"(?i)<img\s[^>]*?src\s*=\s*["']([^"']+)["']"#).unwrap();
+    static ref ENTITY: Regex = Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap();
+}
+
+const IMAGE_EXTENSIONS: [&str; 5] = [".jpg", ".jpeg", ".png", ".webp", ".bmp"];
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Parameters {
+    feeds: Vec<String>,
+}
+impl TryFrom<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(rmp_serde::from_slice(&value)?)
+    }
+}
+impl TryInto<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Vec<u8>, Self::Error> {
+        Ok(to_vec(&self)?)
+    }
+}
+
+struct FeedSource {
+    instances: HashMap<String, Parameters>,
+    client: Client,
+    /// Feeds each instance couldn't fetch, until the host takes them
+    warnings: HashMap<String, Vec<String>>,
+}
+
+impl ReddwSourceTrait<Parameters> for FeedSource {
+    async fn get_name(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(NAME.to_owned())
+    }
+
+    async fn get_assets(&mut self) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+        Ok(HashMap::new())
+    }
+
+    async fn inspect_instance(&mut self, id: String) -> Result<Parameters, Box<dyn Error>> {
+        let parameters = self
+            .instances
+            .get(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+            .clone();
+        Ok(parameters)
+    }
+
+    async fn register_instance(
+        &mut self,
+        id: String,
+        parameters: Parameters,
+    ) -> Result<bool, Box<dyn Error>> {
+        if id.contains("_") {
+            Err(anyhow!(
+                "Invalid instance ID. Instance IDs cannot contain underscores"
+            ))?
+        }
+        for feed in &parameters.feeds {
+            Url::parse(feed)?;
+        }
+        let overrode_existing = self.instances.insert(id, parameters).is_some();
+        Ok(overrode_existing)
+    }
+
+    async fn deregister_instance(&mut self, id: String) -> Result<(), Box<dyn Error>> {
+        self.instances
+            .remove(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?;
+        self.warnings.remove(&id);
+        Ok(())
+    }
+
+    async fn get_wallpapers(
+        &mut self,
+        id: String,
+        wallpaper_ids: Vec<String>,
+    ) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
+        let parameters = {
+            self.instances
+                .get(&id)
+                .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+                .clone()
+        };
+        let source = format!("{NAME}_{id}");
+        let mut wallpapers = Vec::new();
+        let mut failures = Vec::new();
+        for feed_url in &parameters.feeds {
+            // One unreachable feed shouldn't keep the others from being used
+            let (feed_url, feed) = match self.fetch_feed(feed_url).await {
+                Ok(fetched) => fetched,
+                Err(err) => {
+                    self.warnings
+                        .entry(id.clone())
+                        .or_default()
+                        .push(format!("Couldn't fetch {feed_url}: {err}"));
+                    failures.push(err);
+                    continue;
+                }
+            };
+            for entry in feed.entries {
+                for wallpaper in entry_wallpapers(entry, &feed_url, &source) {
+                    // The same image can show up in several items and feeds
+                    if !wallpaper_ids.contains(&wallpaper.id)
+                        && !wallpapers.iter().any(|w: &Wallpaper| w.id == wallpaper.id)
+                    {
+                        wallpapers.push(wallpaper);
+                    }
+                }
+            }
+        }
+        // The request only fails when every feed did
+        if failures.len() == parameters.feeds.len()
+            && let Some(err) = failures.pop()
+        {
+            Err(err)?;
+        }
+        Ok(wallpapers)
+    }
+
+    async fn take_warnings(&mut self, id: String) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.warnings.remove(&id).unwrap_or_default())
+    }
+
+    async fn get_instances(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .instances
+            .keys()
+            .clone()
+            .into_iter()
+            .map(String::to_owned)
+            .collect())
+    }
+}
+static NAME: &str = "Feed";
+
+#[tokio::main]
+async fn main() {
+    FeedSource {
+        instances: HashMap::new(),
+        client: Client::new(),
+        warnings: HashMap::new(),
+    }
+    .main_loop()
+    .await
+}
+
+impl FeedSource {
+    async fn fetch_feed(&self, feed_url: &str) -> Result<(Url, Feed)> {
+        let feed_url = Url::parse(feed_url)?;
+        let response = self.client.get(feed_url.clone()).send().await?;
+        if !response.status().is_success() {
+            Err(anyhow!(
+                "HTTP {} while fetching {feed_url}",
+                response.status()
+            ))?;
+        }
+        let feed = feed_rs::parser::parse(response.bytes().await?.as_ref())?;
+        Ok((feed_url, feed))
+    }
+}
+
+fn looks_like_image(url: &Url) -> bool {
+    let path = url.path().to_lowercase();
+    IMAGE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+/// Resolves the character references HTML attributes may contain, such as `&amp;` in query strings
+fn decode_entities(text: &str) -> String {
+    ENTITY
+        .replace_all(text, |captures: &regex::Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or(entity.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or(entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            // Unknown entities are left as they were
+            decoded.map_or_else(|| captures[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// Images from an item's enclosures and media tags, followed by ones embedded in its content
+fn entry_wallpapers(entry: Entry, feed_url: &Url, source: &str) -> Vec<Wallpaper> {
+    let info_url = entry
+        .links
+        .iter()
+        .find(|link| link.rel.as_deref().map_or(true, |rel| rel == "alternate"))
+        .map(|link| link.href.clone());
+    // Relative links in content are relative to the item's page
+    let base = info_url
+        .as_deref()
+        .and_then(|url| Url::parse(url).ok())
+        .unwrap_or(feed_url.clone());
+    let name = entry.title.map(|title| title.content);
+
+    // Atom feeds attach files as links rather than media
+    let enclosures = entry
+        .links
+        .iter()
+        .filter(|link| link.rel.as_deref() == Some("enclosure"))
+        .filter_map(|link| {
+            let url = base.join(&link.href).ok()?;
+            let is_image = link
+                .media_type
+                .as_deref()
+                .map_or_else(|| looks_like_image(&url), |mime| mime.starts_with("image/"));
+            is_image.then_some(url)
+        })
+        .collect::<Vec<_>>();
+
+    let media = entry
+        .media
+        .into_iter()
+        .flat_map(|media| media.content)
+        .filter_map(|content| {
+            let url = content.url?;
+            let is_image = content
+                .content_type
+                .map_or_else(|| looks_like_image(&url), |mime| mime.type_() == "image");
+            is_image.then_some(url)
+        });
+    let inline = [
+        entry.content.and_then(|content| content.body),
+        entry.summary.map(|summary| summary.content),
+    ]
+    .into_iter()
+    .flatten()
+    .flat_map(|html| {
+        IMG_TAG
+            .captures_iter(&html)
+            .filter_map(|captures| base.join(&decode_entities(&captures[1])).ok())
+            .collect::<Vec<_>>()
+    });
+
+    let mut urls: Vec<Url> = Vec::new();
+    for url in enclosures.into_iter().chain(media).chain(inline) {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls.into_iter()
+        .map(|url| {
+            Wallpaper::new(
+                sha256::digest(url.as_str()),
+                name.clone(),
+                url.to_string(),
+                info_url.clone(),
+                source.to_string(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    const RSS: &str = include_str!("../fixtures/rss.xml");
+    const ATOM: &str = include_str!("../fixtures/atom.xml");
+    /// Nothing listens on port 1, so connecting fails straight away
+    const UNREACHABLE: &str = "http://127.0.0.1:1/feed.xml";
+
+    fn parse(fixture: &str, feed_url: &str) -> Vec<Vec<Wallpaper>> {
+        let feed = feed_rs::parser::parse(fixture.as_bytes()).unwrap();
+        let feed_url = Url::parse(feed_url).unwrap();
+        feed.entries
+            .into_iter()
+            .map(|entry| entry_wallpapers(entry, &feed_url, "Feed_test"))
+            .collect()
+    }
+
+    fn data_urls(wallpapers: &[Wallpaper]) -> Vec<&str> {
+        wallpapers.iter().map(|w| w.data_url.as_str()).collect()
+    }
+
+    /// Serves `body` as an RSS feed on a local port, returning its URL
+    fn serve(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                // The request has no body, so the headers end it
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/rss+xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    async fn source(feeds: Vec<String>) -> FeedSource {
+        let mut source = FeedSource {
+            instances: HashMap::new(),
+            client: Client::new(),
+            warnings: HashMap::new(),
+        };
+        source
+            .register_instance("test".to_string(), Parameters { feeds })
+            .await
+            .unwrap();
+        source
+    }
+
+    #[test]
+    fn rss_images() {
+        let entries = parse(RSS, "https://photos.example.com/feed.xml");
+        // Enclosures come first, audio ones are skipped, and relative images follow the item's link
+        assert_eq!(
+            data_urls(&entries[0]),
+            [
+                "https://cdn.example.com/harbour.jpg",
+                "https://photos.example.com/images/harbour-full.png?w=3840&h=2160",
+            ]
+        );
+        // The media tag and the same image in the description make one wallpaper
+        assert_eq!(
+            data_urls(&entries[1]),
+            [
+                "https://cdn.example.com/forest.webp",
+                "https://photos.example.com/posts/thumbs/forest.jpeg",
+            ]
+        );
+    }
+
+    #[test]
+    fn rss_fields() {
+        let entries = parse(RSS, "https://photos.example.com/feed.xml");
+        let first = &entries[0][0];
+        assert_eq!(
+            first.id,
+            sha256::digest("https://cdn.example.com/harbour.jpg")
+        );
+        assert_eq!(first.name.as_deref(), Some("Harbour at night"));
+        assert_eq!(
+            first.info_url.as_deref(),
+            Some("https://photos.example.com/posts/harbour")
+        );
+        assert_eq!(first.source, "Feed_test");
+    }
+
+    #[test]
+    fn atom_enclosure_links() {
+        let entries = parse(ATOM, "https://walls.example.org/feed.atom");
+        assert_eq!(
+            data_urls(&entries[0]),
+            [
+                "https://walls.example.org/files/dunes.png",
+                // Untyped enclosures are judged by their extension
+                "https://walls.example.org/files/dunes.bmp",
+                "https://walls.example.org/files/dunes-preview.jpg?size=large&crop=0",
+            ]
+        );
+        assert_eq!(
+            entries[0][0].info_url.as_deref(),
+            Some("https://walls.example.org/dunes")
+        );
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(
+            decode_entities("a.jpg?x=1&amp;y=&#50;&amp;z=&#x33;&lt;&gt;&quot;&apos;"),
+            "a.jpg?x=1&y=2&z=3<>\"'"
+        );
+        // Unknown and malformed references are kept
+        assert_eq!(
+            decode_entities("&nbsp;&#xZZ; & &amp"),
+            "&nbsp;&#xZZ; & &amp"
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_feed_is_a_warning() {
+        let mut source = source(vec![UNREACHABLE.to_string(), serve(RSS)]).await;
+        let wallpapers = source
+            .get_wallpapers("test".to_string(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(wallpapers.len(), 4);
+        let warnings = source.take_warnings("test".to_string()).await.unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with(&format!("Couldn't fetch {UNREACHABLE}")));
+        // Taking the warnings clears them
+        assert!(source
+            .take_warnings("test".to_string())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn fails_when_every_feed_does() {
+        let mut source = source(vec![UNREACHABLE.to_string()]).await;
+        assert!(source
+            .get_wallpapers("test".to_string(), Vec::new())
+            .await
+            .is_err());
+    }
+}
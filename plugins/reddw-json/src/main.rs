@@ -0,0 +1,420 @@
+#![feature(async_closure, let_chains)]
+#![windows_subsystem = "windows"]
+use anyhow::{anyhow, Result};
+use reddw_source_plugin::{ReddwSourceTrait, Wallpaper};
+use reqwest::{Client, Url};
+use rmp_serde::to_vec;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::{collections::HashMap, error::Error};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Pagination {
+    /// Value of `{page}` in the first request
+    #[serde(default)]
+    start: i64,
+    /// How much `{page}` grows by with each request
+    #[serde(default = "default_step")]
+    step: i64,
+    #[serde(default = "default_max_pages")]
+    max_pages: u32,
+}
+fn default_step() -> i64 {
+    1
+}
+fn default_max_pages() -> u32 {
+    5
+}
+
+/// Describes where in an API's response the wallpapers are.
+/// `items` is evaluated against the whole response, the rest against each item
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Parameters {
+    /// URL to request. `{page}` is substituted when paginating.
+    /// file:// URLs are read from disk, which is handy for fixtures
+    url: String,
+    #[serde(default)]
+    pagination: Option<Pagination>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// e.g. `$.images[*]`
+    items: String,
+    /// Relative URLs are resolved against the request URL
+    image_url: String,
+    /// Falls back to a hash of the image URL
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    info_url: Option<String>,
+}
+impl TryFrom<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(rmp_serde::from_slice(&value)?)
+    }
+}
+impl TryInto<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Vec<u8>, Self::Error> {
+        Ok(to_vec(&self)?)
+    }
+}
+
+/// An instance's parameters with its paths parsed up front
+#[derive(Clone)]
+struct Instance {
+    parameters: Parameters,
+    items: JsonPath,
+    image_url: JsonPath,
+    id: Option<JsonPath>,
+    title: Option<JsonPath>,
+    info_url: Option<JsonPath>,
+}
+
+impl TryFrom<Parameters> for Instance {
+    type Error = anyhow::Error;
+
+    fn try_from(parameters: Parameters) -> Result<Self, Self::Error> {
+        let parse = |path: &str| {
+            JsonPath::parse(path).map_err(|err| anyhow!("Invalid path \"{path}\": {err}"))
+        };
+        let optional = |path: &Option<String>| path.as_deref().map(parse).transpose();
+        Ok(Instance {
+            items: parse(&parameters.items)?,
+            image_url: parse(&parameters.image_url)?,
+            id: optional(&parameters.id)?,
+            title: optional(&parameters.title)?,
+            info_url: optional(&parameters.info_url)?,
+            parameters,
+        })
+    }
+}
+
+struct JsonSource {
+    instances: HashMap<String, Instance>,
+    client: Client,
+}
+
+impl ReddwSourceTrait<Parameters> for JsonSource {
+    async fn get_name(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(NAME.to_owned())
+    }
+
+    async fn get_assets(&mut self) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+        Ok(HashMap::new())
+    }
+
+    async fn inspect_instance(&mut self, id: String) -> Result<Parameters, Box<dyn Error>> {
+        let parameters = self
+            .instances
+            .get(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+            .parameters
+            .clone();
+        Ok(parameters)
+    }
+
+    async fn register_instance(
+        &mut self,
+        id: String,
+        parameters: Parameters,
+    ) -> Result<bool, Box<dyn Error>> {
+        if id.contains("_") {
+            Err(anyhow!(
+                "Invalid instance ID. Instance IDs cannot contain underscores"
+            ))?
+        }
+        let instance = Instance::try_from(parameters)?;
+        let overrode_existing = self.instances.insert(id, instance).is_some();
+        Ok(overrode_existing)
+    }
+
+    async fn deregister_instance(&mut self, id: String) -> Result<(), Box<dyn Error>> {
+        self.instances
+            .remove(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?;
+        Ok(())
+    }
+
+    async fn get_wallpapers(
+        &mut self,
+        id: String,
+        wallpaper_ids: Vec<String>,
+    ) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
+        let instance = {
+            self.instances
+                .get(&id)
+                .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+                .clone()
+        };
+        let source = format!("{NAME}_{id}");
+        let pages = match &instance.parameters.pagination {
+            Some(pagination) => (0..pagination.max_pages as i64)
+                .map(|n| Some(pagination.start + n * pagination.step))
+                .collect(),
+            None => vec![None],
+        };
+        let mut wallpapers = Vec::new();
+        for page in pages {
+            let mut url = instance.parameters.url.clone();
+            if let Some(page) = page {
+                url = url.replace("{page}", &page.to_string());
+            }
+            let url = Url::parse(&url)?;
+            let response = self.fetch(&url, &instance.parameters.headers).await?;
+            wallpapers.extend(
+                response_wallpapers(&instance, &response, &url, &source)
+                    .into_iter()
+                    .filter(|wallpaper| !wallpaper_ids.contains(&wallpaper.id)),
+            );
+            if !wallpapers.is_empty() {
+                break;
+            }
+        }
+        Ok(wallpapers)
+    }
+
+    async fn take_warnings(&mut self, _id: String) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_instances(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .instances
+            .keys()
+            .clone()
+            .into_iter()
+            .map(String::to_owned)
+            .collect())
+    }
+}
+static NAME: &str = "JSON";
+
+#[tokio::main]
+async fn main() {
+    JsonSource {
+        instances: HashMap::new(),
+        client: Client::new(),
+    }
+    .main_loop()
+    .await
+}
+
+impl JsonSource {
+    async fn fetch(&self, url: &Url, headers: &HashMap<String, String>) -> Result<Value> {
+        if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid file URL {url}"))?;
+            return Ok(serde_json::from_slice(&tokio::fs::read(path).await?)?);
+        }
+        let mut request = self.client.get(url.clone());
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            Err(anyhow!("HTTP {} while fetching {url}", response.status()))?;
+        }
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+}
+
+/// The first value a path selects, as a string
+fn select_string(path: &JsonPath, value: &Value) -> Option<String> {
+    match path.query(value).all().into_iter().next()? {
+        Value::String(string) => Some(string.to_owned()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+fn response_wallpapers(
+    instance: &Instance,
+    response: &Value,
+    url: &Url,
+    source: &str,
+) -> Vec<Wallpaper> {
+    instance
+        .items
+        .query(response)
+        .all()
+        .into_iter()
+        .filter_map(|item| {
+            let image_url = url
+                .join(&select_string(&instance.image_url, item)?)
+                .ok()?
+                .to_string();
+            let resolve = |path: &Option<JsonPath>| {
+                let value = select_string(path.as_ref()?, item)?;
+                Some(url.join(&value).map_or(value, |u| u.to_string()))
+            };
+            let id = instance
+                .id
+                .as_ref()
+                .and_then(|path| select_string(path, item))
+                .unwrap_or_else(|| sha256::digest(image_url.as_str()));
+            let title = instance
+                .title
+                .as_ref()
+                .and_then(|path| select_string(path, item));
+            Some(Wallpaper::new(
+                id,
+                title,
+                image_url,
+                resolve(&instance.info_url),
+                source.to_string(),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GALLERY: &str = include_str!("../fixtures/gallery.json");
+
+    fn instance() -> Instance {
+        Instance::try_from(Parameters {
+            url: "https://example.com/api/gallery".to_string(),
+            items: "$.images[*]".to_string(),
+            image_url: "$.url".to_string(),
+            id: Some("$.id".to_string()),
+            title: Some("$.title".to_string()),
+            info_url: Some("$.link".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn gallery(instance: &Instance) -> Vec<Wallpaper> {
+        let response = serde_json::from_str(GALLERY).unwrap();
+        let url = Url::parse(&instance.parameters.url).unwrap();
+        response_wallpapers(instance, &response, &url, "JSON_test")
+    }
+
+    /// An instance reading `page-{page}.json` from the fixtures
+    async fn paginated(pagination: Pagination) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
+        let mut source = JsonSource {
+            instances: HashMap::new(),
+            client: Client::new(),
+        };
+        let parameters = Parameters {
+            url: format!(
+                "file://{}/fixtures/page-{{page}}.json",
+                env!("CARGO_MANIFEST_DIR")
+            ),
+            pagination: Some(pagination),
+            items: "$.images[*]".to_string(),
+            image_url: "$.url".to_string(),
+            id: Some("$.id".to_string()),
+            ..Default::default()
+        };
+        source
+            .register_instance("test".to_string(), parameters)
+            .await?;
+        source.get_wallpapers("test".to_string(), Vec::new()).await
+    }
+
+    #[test]
+    fn extracts_fields() {
+        let wallpapers = gallery(&instance());
+        let first = &wallpapers[0];
+        // Numbers are taken as strings
+        assert_eq!(first.id, "1");
+        assert_eq!(first.name.as_deref(), Some("Harbour at night"));
+        assert_eq!(first.data_url, "https://example.com/images/1.jpg");
+        assert_eq!(
+            first.info_url.as_deref(),
+            Some("https://example.com/view/1")
+        );
+        assert_eq!(first.source, "JSON_test");
+    }
+
+    #[test]
+    fn falls_back_on_missing_fields() {
+        let wallpapers = gallery(&instance());
+        let second = &wallpapers[1];
+        assert_eq!(second.id, sha256::digest("https://cdn.example.com/2.png"));
+        assert_eq!(second.name, None);
+        assert_eq!(second.info_url, None);
+    }
+
+    #[test]
+    fn skips_items_without_an_image() {
+        // Neither a missing URL nor one which isn't a string makes a wallpaper
+        assert_eq!(gallery(&instance()).len(), 2);
+    }
+
+    #[test]
+    fn optional_paths_can_be_left_out() {
+        let instance = Instance::try_from(Parameters {
+            id: None,
+            title: None,
+            info_url: None,
+            ..instance().parameters
+        })
+        .unwrap();
+        let wallpapers = gallery(&instance);
+        assert_eq!(
+            wallpapers[0].id,
+            sha256::digest("https://example.com/images/1.jpg")
+        );
+        assert_eq!(wallpapers[0].name, None);
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        let parameters = Parameters {
+            items: "images[".to_string(),
+            ..instance().parameters
+        };
+        assert!(Instance::try_from(parameters).is_err());
+    }
+
+    #[tokio::test]
+    async fn pages_until_wallpapers_turn_up() {
+        // The first page is empty
+        let wallpapers = paginated(Pagination {
+            start: 0,
+            step: 1,
+            max_pages: 5,
+        })
+        .await
+        .unwrap();
+        assert_eq!(wallpapers.len(), 1);
+        assert_eq!(wallpapers[0].id, "p1");
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_pages() {
+        let wallpapers = paginated(Pagination {
+            start: 0,
+            step: 1,
+            max_pages: 1,
+        })
+        .await
+        .unwrap();
+        assert!(wallpapers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fails_past_the_last_page() {
+        // Goes from the empty first page straight to one which doesn't exist
+        let result = paginated(Pagination {
+            start: 0,
+            step: 2,
+            max_pages: 2,
+        })
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,393 @@
+#![feature(async_closure, let_chains)]
+#![windows_subsystem = "windows"]
+use anyhow::{anyhow, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use rand::seq::SliceRandom;
+use reddw_source_plugin::{ReddwSourceTrait, Wallpaper};
+use rmp_serde::to_vec;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use url::Url;
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum SortOrder {
+    #[default]
+    Random,
+    NameAscending,
+    NameDescending,
+    OldestFirst,
+    NewestFirst,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Parameters {
+    directories: Vec<PathBuf>,
+    /// Whether to descend into subdirectories
+    #[serde(default)]
+    recursive: bool,
+    /// Globs (relative to the directory) a file has to match. Every image matches if empty
+    #[serde(default)]
+    include: Vec<String>,
+    /// Globs (relative to the directory) which rule a file out
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    sort: SortOrder,
+    /// How many wallpapers to hand over per request
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+}
+fn default_batch_size() -> usize {
+    50
+}
+impl TryFrom<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(rmp_serde::from_slice(&value)?)
+    }
+}
+impl TryInto<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Vec<u8>, Self::Error> {
+        Ok(to_vec(&self)?)
+    }
+}
+
+struct LocalSource {
+    instances: HashMap<String, Parameters>,
+    /// Directories each instance had to skip, until the host takes them
+    warnings: HashMap<String, Vec<String>>,
+}
+
+impl ReddwSourceTrait<Parameters> for LocalSource {
+    async fn get_name(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(NAME.to_owned())
+    }
+
+    async fn get_assets(&mut self) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+        Ok(HashMap::new())
+    }
+
+    async fn inspect_instance(&mut self, id: String) -> Result<Parameters, Box<dyn Error>> {
+        let parameters = self
+            .instances
+            .get(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+            .clone();
+        Ok(parameters)
+    }
+
+    async fn register_instance(
+        &mut self,
+        id: String,
+        parameters: Parameters,
+    ) -> Result<bool, Box<dyn Error>> {
+        if id.contains("_") {
+            Err(anyhow!(
+                "Invalid instance ID. Instance IDs cannot contain underscores"
+            ))?
+        }
+        // Fail early on invalid globs
+        glob_set(&parameters.include)?;
+        glob_set(&parameters.exclude)?;
+        let overrode_existing = self.instances.insert(id, parameters).is_some();
+        Ok(overrode_existing)
+    }
+
+    async fn deregister_instance(&mut self, id: String) -> Result<(), Box<dyn Error>> {
+        self.instances
+            .remove(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?;
+        self.warnings.remove(&id);
+        Ok(())
+    }
+
+    async fn get_wallpapers(
+        &mut self,
+        id: String,
+        wallpaper_ids: Vec<String>,
+    ) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
+        let parameters = {
+            self.instances
+                .get(&id)
+                .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+                .clone()
+        };
+        let source = format!("{NAME}_{id}");
+        let (files, skipped) = find_images(&parameters)?;
+        if !skipped.is_empty() {
+            self.warnings.entry(id.clone()).or_default().extend(skipped);
+        }
+        let mut files = files
+            .into_iter()
+            .filter(|(path, _)| !wallpaper_ids.contains(&file_id(path)))
+            .collect::<Vec<_>>();
+        match parameters.sort {
+            SortOrder::Random => files.shuffle(&mut rand::thread_rng()),
+            SortOrder::NameAscending => files.sort_by(|(a, _), (b, _)| a.cmp(b)),
+            SortOrder::NameDescending => files.sort_by(|(a, _), (b, _)| b.cmp(a)),
+            SortOrder::OldestFirst => files.sort_by_key(|(_, modified)| *modified),
+            SortOrder::NewestFirst => files.sort_by(|(_, a), (_, b)| b.cmp(a)),
+        }
+        let wallpapers = files
+            .into_iter()
+            .take(parameters.batch_size)
+            .filter_map(|(path, _)| {
+                let url = Url::from_file_path(&path).ok()?.to_string();
+                Some(Wallpaper::new(
+                    file_id(&path),
+                    path.file_stem().map(|s| s.to_string_lossy().to_string()),
+                    url.clone(),
+                    Some(url),
+                    source.to_string(),
+                ))
+            })
+            .collect();
+        Ok(wallpapers)
+    }
+
+    async fn take_warnings(&mut self, id: String) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.warnings.remove(&id).unwrap_or_default())
+    }
+
+    async fn get_instances(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .instances
+            .keys()
+            .clone()
+            .into_iter()
+            .map(String::to_owned)
+            .collect())
+    }
+}
+static NAME: &str = "Local";
+
+#[tokio::main]
+async fn main() {
+    LocalSource {
+        instances: HashMap::new(),
+        warnings: HashMap::new(),
+    }
+    .main_loop()
+    .await
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Stable ID for a file, based on its absolute path
+fn file_id(path: &Path) -> String {
+    sha256::digest(path.to_string_lossy().as_ref())
+}
+
+/// Image paths along with their modification times
+type Images = Vec<(PathBuf, SystemTime)>;
+
+/// Every image under the instance's directories which passes its filters.
+/// Also returns why any directories were skipped
+fn find_images(parameters: &Parameters) -> Result<(Images, Vec<String>)> {
+    let include = glob_set(&parameters.include)?;
+    let exclude = glob_set(&parameters.exclude)?;
+    let mut images = Vec::new();
+    let mut skipped = Vec::new();
+    for directory in &parameters.directories {
+        // A missing directory, like an unmounted drive, shouldn't keep the rest from being used
+        let directory = match directory.canonicalize() {
+            Ok(directory) => directory,
+            Err(err) => {
+                skipped.push(format!("Skipping {}: {err}", directory.display()));
+                continue;
+            }
+        };
+        let walker = WalkDir::new(&directory)
+            .follow_links(true)
+            .max_depth(if parameters.recursive { usize::MAX } else { 1 });
+        for entry in walker.into_iter().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !entry.file_type().is_file()
+                || !mime_guess::from_path(path)
+                    .iter()
+                    .any(|mime| mime.type_() == mime_guess::mime::IMAGE)
+            {
+                continue;
+            }
+            let relative = path.strip_prefix(&directory).unwrap_or(path);
+            if (!parameters.include.is_empty() && !include.is_match(relative))
+                || exclude.is_match(relative)
+            {
+                continue;
+            }
+            let modified = entry
+                .metadata()
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            images.push((path.to_path_buf(), modified));
+        }
+    }
+    Ok((images, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    /// A fresh directory holding empty `files`, named after the test using it
+    fn directory(name: &str, files: &[&str]) -> PathBuf {
+        let directory = env::temp_dir().join(format!("reddw-local-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        for file in files {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, []).unwrap();
+        }
+        directory.canonicalize().unwrap()
+    }
+
+    /// Names of the images found, relative to `directory`
+    fn found(directory: &Path, parameters: &Parameters) -> Vec<String> {
+        let (images, skipped) = find_images(parameters).unwrap();
+        assert!(skipped.is_empty());
+        let mut names = images
+            .into_iter()
+            .map(|(path, _)| {
+                path.strip_prefix(directory)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    const FILES: [&str; 5] = [
+        "a.jpg",
+        "b.PNG",
+        "notes.txt",
+        "nested/c.webp",
+        "nested/drafts/d.jpeg",
+    ];
+
+    #[test]
+    fn only_images_at_the_top_level() {
+        let directory = directory("top-level", &FILES);
+        let parameters = Parameters {
+            directories: vec![directory.clone()],
+            ..Default::default()
+        };
+        assert_eq!(found(&directory, &parameters), ["a.jpg", "b.PNG"]);
+    }
+
+    #[test]
+    fn recursive() {
+        let directory = directory("recursive", &FILES);
+        let parameters = Parameters {
+            directories: vec![directory.clone()],
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            found(&directory, &parameters),
+            ["a.jpg", "b.PNG", "nested/c.webp", "nested/drafts/d.jpeg"]
+        );
+    }
+
+    #[test]
+    fn globs() {
+        let directory = directory("globs", &FILES);
+        let parameters = Parameters {
+            directories: vec![directory.clone()],
+            recursive: true,
+            include: vec!["nested/**".to_string()],
+            exclude: vec!["**/drafts/**".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(found(&directory, &parameters), ["nested/c.webp"]);
+    }
+
+    #[tokio::test]
+    async fn missing_directory_is_a_warning() {
+        let directory = directory("missing", &["a.jpg"]);
+        let missing = directory.join("unmounted");
+        let mut source = LocalSource {
+            instances: HashMap::new(),
+            warnings: HashMap::new(),
+        };
+        let parameters = Parameters {
+            directories: vec![missing.clone(), directory.clone()],
+            batch_size: 50,
+            ..Default::default()
+        };
+        source
+            .register_instance("test".to_string(), parameters)
+            .await
+            .unwrap();
+        let wallpapers = source
+            .get_wallpapers("test".to_string(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(wallpapers.len(), 1);
+        let warnings = source.take_warnings("test".to_string()).await.unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with(&format!("Skipping {}", missing.display())));
+        assert!(source
+            .take_warnings("test".to_string())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn known_files_are_left_out() {
+        let directory = directory("known", &["a.jpg", "b.png"]);
+        let mut source = LocalSource {
+            instances: HashMap::new(),
+            warnings: HashMap::new(),
+        };
+        let parameters = Parameters {
+            directories: vec![directory.clone()],
+            sort: SortOrder::NameAscending,
+            batch_size: 50,
+            ..Default::default()
+        };
+        source
+            .register_instance("test".to_string(), parameters)
+            .await
+            .unwrap();
+        let known = vec![file_id(&directory.join("a.jpg"))];
+        let wallpapers = source
+            .get_wallpapers("test".to_string(), known)
+            .await
+            .unwrap();
+        assert_eq!(wallpapers.len(), 1);
+        assert_eq!(wallpapers[0].id, file_id(&directory.join("b.png")));
+        assert_eq!(wallpapers[0].name.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn file_id_is_the_path_hash() {
+        let path = Path::new("/home/user/Pictures/a.jpg");
+        assert_eq!(file_id(path), sha256::digest("/home/user/Pictures/a.jpg"));
+        assert_ne!(
+            file_id(path),
+            file_id(Path::new("/home/user/Pictures/b.jpg"))
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct Listing {
+    pub data: ListingData,
+}
+
+#[derive(Deserialize)]
+pub struct ListingData {
+    pub after: Option<String>,
+    pub children: Vec<Child>,
+}
+
+#[derive(Deserialize)]
+pub struct Child {
+    pub data: Post,
+}
+
+#[derive(Deserialize)]
+pub struct Post {
+    pub id: String,
+    pub title: String,
+    pub permalink: String,
+    pub author: Option<String>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub over_18: bool,
+    pub post_hint: Option<String>,
+    #[serde(default)]
+    pub is_gallery: bool,
+    pub preview: Option<Preview>,
+    pub media_metadata: Option<HashMap<String, MediaMetadata>>,
+    pub gallery_data: Option<GalleryData>,
+}
+
+#[derive(Deserialize)]
+pub struct Preview {
+    pub images: Vec<PreviewImage>,
+}
+
+#[derive(Deserialize)]
+pub struct PreviewImage {
+    pub source: ImageSource,
+}
+
+#[derive(Deserialize)]
+pub struct ImageSource {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A gallery item's metadata. Reddit abbreviates these fields
+#[derive(Deserialize)]
+pub struct MediaMetadata {
+    pub status: Option<String>,
+    /// Kind of media, e.g. "Image" or "AnimatedImage"
+    pub e: Option<String>,
+    /// Source image
+    pub s: Option<MediaSource>,
+}
+
+#[derive(Deserialize)]
+pub struct MediaSource {
+    pub u: Option<String>,
+    pub x: Option<u32>,
+    pub y: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct GalleryData {
+    pub items: Vec<GalleryItem>,
+}
+
+#[derive(Deserialize)]
+pub struct GalleryItem {
+    pub media_id: String,
+}
@@ -0,0 +1,543 @@
+#![feature(async_closure, let_chains)]
+#![windows_subsystem = "windows"]
+use anyhow::{anyhow, Result};
+use listing::{Listing, Post};
+use reddw_source_plugin::{ReddwSourceTrait, Wallpaper};
+use reqwest::{Client, StatusCode, Url};
+use rmp_serde::to_vec;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error};
+mod listing;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum Sort {
+    #[default]
+    Hot,
+    New,
+    Top,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum TimeWindow {
+    Hour,
+    #[default]
+    Day,
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum Nsfw {
+    #[default]
+    Exclude,
+    Include,
+    Only,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Parameters {
+    subreddits: Vec<String>,
+    #[serde(default)]
+    sort: Sort,
+    /// Only used when sorting by top
+    #[serde(default)]
+    time: TimeWindow,
+    #[serde(default)]
+    min_width: u32,
+    #[serde(default)]
+    min_height: u32,
+    #[serde(default)]
+    nsfw: Nsfw,
+    /// Where to fetch listings from - only worth changing for testing
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    /// How many listing pages to go through per request before giving up
+    #[serde(default = "default_max_pages")]
+    max_pages: u32,
+}
+fn default_base_url() -> String {
+    "https://www.reddit.com".to_string()
+}
+fn default_max_pages() -> u32 {
+    5
+}
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            subreddits: Vec::new(),
+            sort: Sort::default(),
+            time: TimeWindow::default(),
+            min_width: 0,
+            min_height: 0,
+            nsfw: Nsfw::default(),
+            base_url: default_base_url(),
+            max_pages: default_max_pages(),
+        }
+    }
+}
+impl TryFrom<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(rmp_serde::from_slice(&value)?)
+    }
+}
+impl TryInto<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Vec<u8>, Self::Error> {
+        Ok(to_vec(&self)?)
+    }
+}
+
+struct RedditSource {
+    instances: HashMap<String, Parameters>,
+    client: Client,
+}
+
+impl ReddwSourceTrait<Parameters> for RedditSource {
+    async fn get_name(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(NAME.to_owned())
+    }
+
+    async fn get_assets(&mut self) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+        Ok(HashMap::new())
+    }
+
+    async fn inspect_instance(&mut self, id: String) -> Result<Parameters, Box<dyn Error>> {
+        let parameters = self
+            .instances
+            .get(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+            .clone();
+        Ok(parameters)
+    }
+
+    async fn register_instance(
+        &mut self,
+        id: String,
+        parameters: Parameters,
+    ) -> Result<bool, Box<dyn Error>> {
+        if id.contains("_") {
+            Err(anyhow!(
+                "Invalid instance ID. Instance IDs cannot contain underscores"
+            ))?
+        }
+        if parameters.subreddits.is_empty() {
+            Err(anyhow!("At least one subreddit is required"))?
+        }
+        Url::parse(&parameters.base_url)?;
+        let overrode_existing = self.instances.insert(id, parameters).is_some();
+        Ok(overrode_existing)
+    }
+
+    async fn deregister_instance(&mut self, id: String) -> Result<(), Box<dyn Error>> {
+        self.instances
+            .remove(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?;
+        Ok(())
+    }
+
+    async fn get_wallpapers(
+        &mut self,
+        id: String,
+        wallpaper_ids: Vec<String>,
+    ) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
+        let parameters = {
+            self.instances
+                .get(&id)
+                .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+                .clone()
+        };
+        let source = format!("{NAME}_{id}");
+        let mut wallpapers = Vec::new();
+        let mut after = None;
+        for _ in 0..parameters.max_pages {
+            let listing = self.listing_page(&parameters, after.as_deref()).await?;
+            wallpapers.extend(
+                listing
+                    .data
+                    .children
+                    .into_iter()
+                    .flat_map(|child| post_wallpapers(child.data, &parameters, &source))
+                    .filter(|wallpaper| !wallpaper_ids.contains(&wallpaper.id)),
+            );
+            after = listing.data.after;
+            if !wallpapers.is_empty() || after.is_none() {
+                break;
+            }
+        }
+        Ok(wallpapers)
+    }
+
+    async fn take_warnings(&mut self, _id: String) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_instances(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .instances
+            .keys()
+            .clone()
+            .into_iter()
+            .map(String::to_owned)
+            .collect())
+    }
+}
+static NAME: &str = "Reddit";
+
+#[tokio::main]
+async fn main() {
+    RedditSource {
+        instances: HashMap::new(),
+        client: Client::builder()
+            // Reddit throttles generic user agents heavily
+            .user_agent(concat!("reddw/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Couldn't build HTTP client"),
+    }
+    .main_loop()
+    .await
+}
+
+impl RedditSource {
+    async fn listing_page(&self, parameters: &Parameters, after: Option<&str>) -> Result<Listing> {
+        let sort = match parameters.sort {
+            Sort::Hot => "hot",
+            Sort::New => "new",
+            Sort::Top => "top",
+        };
+        // Joining onto a URL without a trailing slash would replace its last segment
+        let base_url = format!("{}/", parameters.base_url.trim_end_matches('/'));
+        let mut url = Url::parse(&base_url)?.join(&format!(
+            "r/{}/{sort}.json",
+            parameters.subreddits.join("+")
+        ))?;
+        url.query_pairs_mut()
+            .append_pair("limit", "100")
+            // Otherwise URLs come HTML escaped
+            .append_pair("raw_json", "1");
+        if parameters.sort == Sort::Top {
+            let time = match parameters.time {
+                TimeWindow::Hour => "hour",
+                TimeWindow::Day => "day",
+                TimeWindow::Week => "week",
+                TimeWindow::Month => "month",
+                TimeWindow::Year => "year",
+                TimeWindow::All => "all",
+            };
+            url.query_pairs_mut().append_pair("t", time);
+        }
+        if let Some(after) = after {
+            url.query_pairs_mut().append_pair("after", after);
+        }
+        let response = self.client.get(url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            Err(anyhow!("Subreddit not found"))?;
+        }
+        if !response.status().is_success() {
+            Err(anyhow!(
+                "HTTP {} while attempting to communicate with Reddit",
+                response.status()
+            ))?;
+        }
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+}
+
+/// Direct images and gallery images of a post which pass the instance's filters
+fn post_wallpapers(post: Post, parameters: &Parameters, source: &str) -> Vec<Wallpaper> {
+    let nsfw_allowed = match parameters.nsfw {
+        Nsfw::Exclude => !post.over_18,
+        Nsfw::Include => true,
+        Nsfw::Only => post.over_18,
+    };
+    if !nsfw_allowed {
+        return Vec::new();
+    }
+    // Unknown dimensions get the benefit of the doubt
+    let big_enough = |dimensions: Option<(u32, u32)>| {
+        dimensions.map_or(true, |(width, height)| {
+            width >= parameters.min_width && height >= parameters.min_height
+        })
+    };
+    let info_url = format!(
+        "{}{}",
+        parameters.base_url.trim_end_matches('/'),
+        post.permalink
+    );
+
+    if post.is_gallery
+        && let (Some(gallery), Some(metadata)) = (&post.gallery_data, &post.media_metadata)
+    {
+        return gallery
+            .items
+            .iter()
+            .filter_map(|item| {
+                let media = metadata.get(&item.media_id)?;
+                if media.status.as_deref().is_some_and(|s| s != "valid")
+                    || media.e.as_deref() != Some("Image")
+                {
+                    return None;
+                }
+                let image = media.s.as_ref()?;
+                let dimensions = image.x.zip(image.y);
+                if !big_enough(dimensions) {
+                    return None;
+                }
+                let mut wallpaper = Wallpaper::new(
+                    format!("{}-{}", post.id, item.media_id),
+                    Some(post.title.clone()),
+                    image.u.clone()?,
+                    Some(info_url.clone()),
+                    source.to_string(),
+                );
+                if let Some((width, height)) = dimensions {
+                    wallpaper = wallpaper.with_dimensions(width as i64, height as i64);
+                }
+                if let Some(author) = &post.author {
+                    wallpaper = wallpaper.with_author(author.to_owned());
+                }
+                Some(wallpaper)
+            })
+            .collect();
+    }
+
+    let Some(url) = post.url else {
+        return Vec::new();
+    };
+    let is_image = post.post_hint.as_deref() == Some("image")
+        || [".jpg", ".jpeg", ".png", ".webp"]
+            .iter()
+            .any(|ext| url.to_lowercase().ends_with(ext));
+    let dimensions = post
+        .preview
+        .and_then(|preview| preview.images.into_iter().next())
+        .map(|image| (image.source.width, image.source.height));
+    if !is_image || !big_enough(dimensions) {
+        return Vec::new();
+    }
+    let mut wallpaper = Wallpaper::new(
+        post.id,
+        Some(post.title),
+        url,
+        Some(info_url),
+        source.to_string(),
+    );
+    if let Some((width, height)) = dimensions {
+        wallpaper = wallpaper.with_dimensions(width as i64, height as i64);
+    }
+    if let Some(author) = post.author {
+        wallpaper = wallpaper.with_author(author);
+    }
+    vec![wallpaper]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    const LISTING: &str = include_str!("../fixtures/listing.json");
+    /// A page with nothing but a self post, which isn't the last one
+    const NO_IMAGES: &str = include_str!("../fixtures/no_images.json");
+
+    fn parameters() -> Parameters {
+        Parameters {
+            subreddits: vec!["wallpapers".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn wallpapers(parameters: &Parameters) -> Vec<Wallpaper> {
+        let listing: Listing = serde_json::from_str(LISTING).unwrap();
+        listing
+            .data
+            .children
+            .into_iter()
+            .flat_map(|child| post_wallpapers(child.data, parameters, "Reddit_test"))
+            .collect()
+    }
+
+    fn ids(wallpapers: &[Wallpaper]) -> Vec<&str> {
+        wallpapers.iter().map(|w| w.id.as_str()).collect()
+    }
+
+    /// Serves `body` to every request on a local port, keeping the request lines
+    fn serve(body: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Listing requests have no body, so the headers end the request
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(request_line.trim().to_string());
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+        (base_url, requests)
+    }
+
+    async fn fetch(parameters: Parameters) -> Vec<Wallpaper> {
+        let mut source = RedditSource {
+            instances: HashMap::new(),
+            client: Client::new(),
+        };
+        source
+            .register_instance("test".to_string(), parameters)
+            .await
+            .unwrap();
+        source
+            .get_wallpapers("test".to_string(), Vec::new())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_listing() {
+        let listing: Listing = serde_json::from_str(LISTING).unwrap();
+        assert_eq!(listing.data.after.as_deref(), Some("t3_a4"));
+        assert_eq!(listing.data.children.len(), 4);
+        let post = &listing.data.children[0].data;
+        assert_eq!(post.id, "a1");
+        assert!(!post.over_18);
+        assert_eq!(post.preview.as_ref().unwrap().images[0].source.width, 3840);
+    }
+
+    #[test]
+    fn excludes_nsfw_by_default() {
+        assert_eq!(ids(&wallpapers(&parameters())), ["a1", "a3-m1"]);
+    }
+
+    #[test]
+    fn includes_nsfw() {
+        let parameters = Parameters {
+            nsfw: Nsfw::Include,
+            ..parameters()
+        };
+        assert_eq!(ids(&wallpapers(&parameters)), ["a1", "a2", "a3-m1"]);
+    }
+
+    #[test]
+    fn only_nsfw() {
+        let parameters = Parameters {
+            nsfw: Nsfw::Only,
+            ..parameters()
+        };
+        assert_eq!(ids(&wallpapers(&parameters)), ["a2"]);
+    }
+
+    #[test]
+    fn expands_galleries() {
+        let wallpapers = wallpapers(&parameters());
+        // Animated and failed items are left out
+        let gallery = wallpapers
+            .iter()
+            .filter(|w| w.id.starts_with("a3"))
+            .collect::<Vec<_>>();
+        assert_eq!(gallery.len(), 1);
+        let image = gallery[0];
+        assert_eq!(image.id, "a3-m1");
+        assert_eq!(image.name.as_deref(), Some("A few shots from Iceland"));
+        assert_eq!(image.data_url, "https://i.redd.it/m1.jpg");
+        assert_eq!((image.width, image.height), (Some(1920), Some(1080)));
+        assert_eq!(image.author.as_deref(), Some("traveller"));
+        assert_eq!(image.source, "Reddit_test");
+    }
+
+    #[test]
+    fn filters_by_size() {
+        let parameters = Parameters {
+            min_width: 2560,
+            ..parameters()
+        };
+        assert_eq!(ids(&wallpapers(&parameters)), ["a1"]);
+    }
+
+    #[test]
+    fn links_to_base_url() {
+        let parameters = Parameters {
+            base_url: "https://old.reddit.com/".to_string(),
+            ..parameters()
+        };
+        let wallpapers = wallpapers(&parameters);
+        assert_eq!(
+            wallpapers[0].info_url.as_deref(),
+            Some(
+                "https://old.reddit.com/r/wallpapers/comments/a1/mountain_lake_at_dawn_3840x2160/"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_from_base_url() {
+        let (base_url, requests) = serve(LISTING);
+        let wallpapers = fetch(Parameters {
+            base_url,
+            ..parameters()
+        })
+        .await;
+        assert_eq!(ids(&wallpapers), ["a1", "a3-m1"]);
+        // The first page had wallpapers, so there was no need for another
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("GET /r/wallpapers/hot.json?limit=100&raw_json=1 "));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_base_url_path() {
+        let (base_url, requests) = serve(LISTING);
+        fetch(Parameters {
+            base_url: format!("{base_url}/proxy"),
+            ..parameters()
+        })
+        .await;
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("GET /proxy/r/wallpapers/hot.json?"));
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_pages() {
+        let (base_url, requests) = serve(NO_IMAGES);
+        let wallpapers = fetch(Parameters {
+            base_url,
+            max_pages: 3,
+            ..parameters()
+        })
+        .await;
+        assert!(wallpapers.is_empty());
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(!requests[0].contains("after="));
+        assert!(requests[1].contains("&after=t3_next "));
+        assert!(requests[2].contains("&after=t3_next "));
+    }
+}
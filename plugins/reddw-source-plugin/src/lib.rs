@@ -26,6 +26,9 @@ pub enum ReddwSource<Parameters: Serialise + Deserialise> {
     DeregisterInstance(String, ()),
     /// Use an instance to get wallpapers
     GetWallpapers(String, Vec<String>, Vec<Wallpaper>),
+    /// Takes the problems an instance ran into since it was last asked, which didn't stop it
+    /// from getting wallpapers - e.g. one of several feeds being unreachable. The host logs them
+    TakeWarnings(String, Vec<String>),
     GetInstances(Vec<String>),
 }
 
@@ -42,6 +45,25 @@ pub struct Wallpaper {
     pub date: NaiveDateTime,
     pub source: String,
     pub was_set: bool,
+    // Optional metadata. Defaulted so plugins built before these were added keep working
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub width: Option<i64>,
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub height: Option<i64>,
+    /// In bytes
+    #[serde(default)]
+    #[ts(type = "number | null")]
+    pub file_size: Option<i64>,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Comma separated
+    #[serde(default)]
+    pub tags: Option<String>,
+    /// Comma separated hex colours, such as `#663399`
+    #[serde(default)]
+    pub colors: Option<String>,
 }
 
 impl Wallpaper {
@@ -60,14 +82,42 @@ impl Wallpaper {
             date: chrono::Utc::now().naive_utc(),
             was_set: false,
             source,
+            width: None,
+            height: None,
+            file_size: None,
+            author: None,
+            tags: None,
+            colors: None,
         }
     }
+    pub fn with_dimensions(mut self, width: i64, height: i64) -> Self {
+        self.width = Some(width);
+        self.height = Some(height);
+        self
+    }
+    pub fn with_file_size(mut self, file_size: i64) -> Self {
+        self.file_size = Some(file_size);
+        self
+    }
+    pub fn with_author(mut self, author: String) -> Self {
+        self.author = Some(author);
+        self
+    }
+    pub fn with_tags(mut self, tags: &[String]) -> Self {
+        self.tags = (!tags.is_empty()).then(|| tags.join(","));
+        self
+    }
+    pub fn with_colors(mut self, colors: &[String]) -> Self {
+        self.colors = (!colors.is_empty()).then(|| colors.join(","));
+        self
+    }
     #[cfg(feature = "host")]
     pub async fn db_insert(self, db: &SqlitePool) -> Result<(), Box<dyn Error>> {
         query!(
             "---sql
-            insert into queue (id, name, data_url, info_url, date, source, was_set) values 
-            ($1, $2, $3, $4, $5, $6, $7)",
+            insert into queue (id, name, data_url, info_url, date, source, was_set,
+                width, height, file_size, author, tags, colors) values 
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
             self.id,
             self.name,
             self.data_url,
@@ -75,6 +125,12 @@ impl Wallpaper {
             self.date,
             self.source,
             self.was_set,
+            self.width,
+            self.height,
+            self.file_size,
+            self.author,
+            self.tags,
+            self.colors,
         )
         .execute(db)
         .await?;
@@ -106,4 +162,4 @@ impl TS for GenericValue {
     fn transparent() -> bool {
         false
     }
-}
\ No newline at end of file
+}
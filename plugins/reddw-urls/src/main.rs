@@ -0,0 +1,250 @@
+#![feature(async_closure, let_chains)]
+#![windows_subsystem = "windows"]
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use reddw_source_plugin::{ReddwSourceTrait, Wallpaper};
+use regex::Regex;
+use reqwest::{Client, Url};
+use rmp_serde::to_vec;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::PathBuf,
+};
+
+lazy_static! {
+    static ref HREF: Regex = Regex::new(r#"(?i)href\s*=\s*["']([^"'#?]+)["']"#).unwrap();
+}
+
+const IMAGE_EXTENSIONS: [&str; 6] = [".jpg", ".jpeg", ".png", ".webp", ".bmp", ".gif"];
+/// How deep recursive directory indexes are followed
+const MAX_INDEX_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum UrlSource {
+    /// URLs given inline
+    List(Vec<String>),
+    /// A local text file with one URL per line
+    File(PathBuf),
+    /// A remote text file with one URL per line
+    RemoteList(String),
+    /// A plain directory listing page, such as an autoindex
+    #[serde(rename_all = "camelCase")]
+    DirectoryIndex {
+        url: String,
+        /// Also look through linked subdirectories
+        #[serde(default)]
+        recursive: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Parameters {
+    sources: Vec<UrlSource>,
+}
+impl TryFrom<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(rmp_serde::from_slice(&value)?)
+    }
+}
+impl TryInto<Vec<u8>> for Parameters {
+    type Error = anyhow::Error;
+
+    fn try_into(self) -> Result<Vec<u8>, Self::Error> {
+        Ok(to_vec(&self)?)
+    }
+}
+
+struct UrlsSource {
+    instances: HashMap<String, Parameters>,
+    client: Client,
+}
+
+impl ReddwSourceTrait<Parameters> for UrlsSource {
+    async fn get_name(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(NAME.to_owned())
+    }
+
+    async fn get_assets(&mut self) -> Result<HashMap<String, Vec<u8>>, Box<dyn Error>> {
+        Ok(HashMap::new())
+    }
+
+    async fn inspect_instance(&mut self, id: String) -> Result<Parameters, Box<dyn Error>> {
+        let parameters = self
+            .instances
+            .get(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+            .clone();
+        Ok(parameters)
+    }
+
+    async fn register_instance(
+        &mut self,
+        id: String,
+        parameters: Parameters,
+    ) -> Result<bool, Box<dyn Error>> {
+        if id.contains("_") {
+            Err(anyhow!(
+                "Invalid instance ID. Instance IDs cannot contain underscores"
+            ))?
+        }
+        let overrode_existing = self.instances.insert(id, parameters).is_some();
+        Ok(overrode_existing)
+    }
+
+    async fn deregister_instance(&mut self, id: String) -> Result<(), Box<dyn Error>> {
+        self.instances
+            .remove(&id)
+            .ok_or(anyhow!("No registered instance under the name \"{id}\""))?;
+        Ok(())
+    }
+
+    async fn get_wallpapers(
+        &mut self,
+        id: String,
+        wallpaper_ids: Vec<String>,
+    ) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
+        let parameters = {
+            self.instances
+                .get(&id)
+                .ok_or(anyhow!("No registered instance under the name \"{id}\""))?
+                .clone()
+        };
+        let source = format!("{NAME}_{id}");
+        let mut links = Vec::new();
+        for url_source in &parameters.sources {
+            links.extend(self.links(url_source).await?);
+        }
+        let mut seen = HashSet::new();
+        let wallpapers = links
+            .into_iter()
+            .filter(|(url, _)| seen.insert(url.clone()))
+            .map(|(url, info_url)| {
+                let name = url
+                    .path_segments()
+                    .and_then(|segments| segments.last())
+                    .filter(|segment| !segment.is_empty())
+                    .map(|segment| segment.to_string());
+                Wallpaper::new(
+                    url_id(&url),
+                    name,
+                    url.to_string(),
+                    info_url.map(|u| u.to_string()),
+                    source.to_string(),
+                )
+            })
+            .filter(|wallpaper| !wallpaper_ids.contains(&wallpaper.id))
+            .collect();
+        Ok(wallpapers)
+    }
+
+    async fn take_warnings(&mut self, _id: String) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_instances(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .instances
+            .keys()
+            .clone()
+            .into_iter()
+            .map(String::to_owned)
+            .collect())
+    }
+}
+static NAME: &str = "URLs";
+
+#[tokio::main]
+async fn main() {
+    UrlsSource {
+        instances: HashMap::new(),
+        client: Client::new(),
+    }
+    .main_loop()
+    .await
+}
+
+/// Stable ID for a wallpaper, derived from its URL
+fn url_id(url: &Url) -> String {
+    sha256::digest(url.as_str())
+}
+
+fn is_image(url: &Url) -> bool {
+    let path = url.path().to_lowercase();
+    IMAGE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+/// Every URL in a plain list, skipping blank lines and `#` comments
+fn parse_list(list: &str) -> Vec<Url> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| Url::parse(line).ok())
+        .collect()
+}
+
+impl UrlsSource {
+    async fn fetch_text(&self, url: &Url) -> Result<String> {
+        let response = self.client.get(url.clone()).send().await?;
+        if !response.status().is_success() {
+            Err(anyhow!("HTTP {} while fetching {url}", response.status()))?;
+        }
+        Ok(response.text().await?)
+    }
+
+    /// Image URLs from a source, along with the page they were found on
+    async fn links(&self, url_source: &UrlSource) -> Result<Vec<(Url, Option<Url>)>> {
+        let links = match url_source {
+            UrlSource::List(urls) => parse_list(&urls.join("\n"))
+                .into_iter()
+                .map(|url| (url, None))
+                .collect(),
+            UrlSource::File(path) => parse_list(&tokio::fs::read_to_string(path).await?)
+                .into_iter()
+                .map(|url| (url, None))
+                .collect(),
+            UrlSource::RemoteList(url) => parse_list(&self.fetch_text(&Url::parse(url)?).await?)
+                .into_iter()
+                .map(|url| (url, None))
+                .collect(),
+            UrlSource::DirectoryIndex { url, recursive } => {
+                self.index_links(Url::parse(url)?, *recursive).await?
+            }
+        };
+        Ok(links)
+    }
+
+    async fn index_links(&self, root: Url, recursive: bool) -> Result<Vec<(Url, Option<Url>)>> {
+        let mut links = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![(root.clone(), 0)];
+        while let Some((page, depth)) = pending.pop() {
+            if !visited.insert(page.clone()) {
+                continue;
+            }
+            let html = self.fetch_text(&page).await?;
+            for captures in HREF.captures_iter(&html) {
+                let Ok(link) = page.join(&captures[1]) else {
+                    continue;
+                };
+                if is_image(&link) {
+                    links.push((link, Some(page.clone())));
+                } else if recursive
+                    && depth < MAX_INDEX_DEPTH
+                    && link.path().ends_with('/')
+                    // Only descend, never back up to parent directories or off to other hosts
+                    && link.as_str().starts_with(page.as_str())
+                    && link != page
+                {
+                    pending.push((link, depth + 1));
+                }
+            }
+        }
+        Ok(links)
+    }
+}
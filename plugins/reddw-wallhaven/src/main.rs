@@ -2,22 +2,227 @@
 #![windows_subsystem = "windows"]
 use anyhow::{anyhow, Result};
 use reddw_source_plugin::{ReddwSourceTrait, Wallpaper};
-use reqwest::{Client, Method, Url};
-use response_data::BaseResponse;
+use reqwest::{header::RETRY_AFTER, Client, StatusCode, Url};
+use response_data::{BaseResponse, Meta};
 use rmp_serde::to_vec;
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error};
+use std::{collections::HashMap, error::Error, time::Duration};
 mod response_data;
 
+/// How many times a rate limited request is retried
+const MAX_RETRIES: u32 = 3;
+/// Used when a rate limited response doesn't say how long to wait
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
 #[derive(RustEmbed)]
 #[folder = "ui/dist/"]
 struct StaticAssets;
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Categories {
+    general: bool,
+    anime: bool,
+    people: bool,
+}
+impl Default for Categories {
+    fn default() -> Self {
+        Self {
+            general: true,
+            anime: true,
+            people: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Purities {
+    sfw: bool,
+    sketchy: bool,
+    /// Requires an API key
+    nsfw: bool,
+}
+impl Default for Purities {
+    fn default() -> Self {
+        Self {
+            sfw: true,
+            sketchy: false,
+            nsfw: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Sorting {
+    #[default]
+    DateAdded,
+    Relevance,
+    Random,
+    Views,
+    Favorites,
+    Toplist,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Order {
+    #[default]
+    Desc,
+    Asc,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum TopRange {
+    Day,
+    ThreeDays,
+    Week,
+    Month,
+    ThreeMonths,
+    SixMonths,
+    Year,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct Parameters {
     tags: Vec<String>,
+    #[serde(default)]
+    categories: Categories,
+    #[serde(default)]
+    purity: Purities,
+    #[serde(default)]
+    sorting: Sorting,
+    #[serde(default)]
+    order: Order,
+    /// Only used with toplist sorting
+    #[serde(default)]
+    top_range: Option<TopRange>,
+    /// Minimum resolution, e.g. "1920x1080"
+    #[serde(default)]
+    atleast: Option<String>,
+    /// Exact resolutions, e.g. "1920x1080"
+    #[serde(default)]
+    resolutions: Vec<String>,
+    /// e.g. "16x9"
+    #[serde(default)]
+    ratios: Vec<String>,
+    /// Hex colours without the #, e.g. "663399"
+    #[serde(default)]
+    colors: Vec<String>,
+    #[serde(default)]
+    api_key: Option<String>,
+    /// Where the API lives - only worth changing for testing
+    #[serde(default = "default_base_url")]
+    base_url: String,
+    /// How many result pages to go through per request before giving up
+    #[serde(default = "default_max_pages")]
+    max_pages: u32,
+}
+fn default_base_url() -> String {
+    "https://wallhaven.cc/api/v1/".to_string()
+}
+fn default_max_pages() -> u32 {
+    5
+}
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            tags: Vec::new(),
+            categories: Categories::default(),
+            purity: Purities::default(),
+            sorting: Sorting::default(),
+            order: Order::default(),
+            top_range: None,
+            atleast: None,
+            resolutions: Vec::new(),
+            ratios: Vec::new(),
+            colors: Vec::new(),
+            api_key: None,
+            base_url: default_base_url(),
+            max_pages: default_max_pages(),
+        }
+    }
+}
+
+impl Parameters {
+    /// Query parameters for a search request
+    fn search_query(&self, page: u32, seed: Option<&str>) -> Vec<(&'static str, String)> {
+        let flags = |flags: &[bool]| {
+            flags
+                .iter()
+                .map(|flag| if *flag { '1' } else { '0' })
+                .collect::<String>()
+        };
+        let mut query = vec![
+            ("q", self.tags.join(" ")),
+            (
+                "categories",
+                flags(&[
+                    self.categories.general,
+                    self.categories.anime,
+                    self.categories.people,
+                ]),
+            ),
+            (
+                "purity",
+                flags(&[self.purity.sfw, self.purity.sketchy, self.purity.nsfw]),
+            ),
+            (
+                "sorting",
+                match self.sorting {
+                    Sorting::DateAdded => "date_added",
+                    Sorting::Relevance => "relevance",
+                    Sorting::Random => "random",
+                    Sorting::Views => "views",
+                    Sorting::Favorites => "favorites",
+                    Sorting::Toplist => "toplist",
+                }
+                .to_string(),
+            ),
+            (
+                "order",
+                match self.order {
+                    Order::Desc => "desc",
+                    Order::Asc => "asc",
+                }
+                .to_string(),
+            ),
+            ("page", page.to_string()),
+        ];
+        if let Some(top_range) = &self.top_range {
+            let top_range = match top_range {
+                TopRange::Day => "1d",
+                TopRange::ThreeDays => "3d",
+                TopRange::Week => "1w",
+                TopRange::Month => "1M",
+                TopRange::ThreeMonths => "3M",
+                TopRange::SixMonths => "6M",
+                TopRange::Year => "1y",
+            };
+            query.push(("topRange", top_range.to_string()));
+        }
+        if let Some(atleast) = &self.atleast {
+            query.push(("atleast", atleast.to_owned()));
+        }
+        for (key, values) in [
+            ("resolutions", &self.resolutions),
+            ("ratios", &self.ratios),
+            ("colors", &self.colors),
+        ] {
+            if !values.is_empty() {
+                query.push((key, values.join(",")));
+            }
+        }
+        if let Some(seed) = seed {
+            query.push(("seed", seed.to_owned()));
+        }
+        query
+    }
 }
 impl TryFrom<Vec<u8>> for Parameters {
     type Error = anyhow::Error;
@@ -72,6 +277,10 @@ impl ReddwSourceTrait<Parameters> for WallHavenSource {
                 "Invalid instance ID. Instance IDs cannot contain underscores"
             ))?
         }
+        if parameters.purity.nsfw && parameters.api_key.is_none() {
+            Err(anyhow!("An API key is required for NSFW results"))?
+        }
+        Url::parse(&parameters.base_url)?;
         let overrode_existing = self.instances.insert(id, parameters).is_some();
         Ok(overrode_existing)
     }
@@ -96,14 +305,25 @@ impl ReddwSourceTrait<Parameters> for WallHavenSource {
         };
         let source = format!("{NAME}_{id}");
         let mut wallpapers = Vec::new();
-        let mut page = 1;
-        while wallpapers.len() == 0 {
-            wallpapers = wallpapers_page(&source, &parameters, &wallpaper_ids, page).await?;
-            page += 1;
+        // Random sorting only pages consistently when every request uses the same seed
+        let mut seed = None;
+        for page in 1..=parameters.max_pages {
+            let meta;
+            (wallpapers, meta) =
+                wallpapers_page(&source, &parameters, &wallpaper_ids, page, seed.as_deref())
+                    .await?;
+            seed = meta.seed.or(seed);
+            if !wallpapers.is_empty() || page as u64 >= meta.last_page {
+                break;
+            }
         }
         Ok(wallpapers)
     }
 
+    async fn take_warnings(&mut self, _id: String) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
     async fn get_instances(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
         Ok(self
             .instances
@@ -130,15 +350,39 @@ async fn wallpapers_page(
     parameters: &Parameters,
     ids: &Vec<String>,
     page: u32,
-) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
-    let terms = parameters.tags.join("+");
-    let request = reqwest::Request::new(
-        Method::GET,
-        Url::parse(&format!(
-            "https://wallhaven.cc/api/v1/search?q={terms}&page={page}"
-        ))?,
-    );
-    let response = Client::new().execute(request).await?;
+    seed: Option<&str>,
+) -> Result<(Vec<Wallpaper>, Meta), Box<dyn Error>> {
+    let mut url = Url::parse(&parameters.base_url)?.join("search")?;
+    url.query_pairs_mut()
+        .extend_pairs(parameters.search_query(page, seed));
+    let mut attempt = 0;
+    let response = loop {
+        let mut request = Client::new().get(url.clone());
+        if let Some(api_key) = &parameters.api_key {
+            request = request.header("X-API-Key", api_key);
+        }
+        let response = request.send().await?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= MAX_RETRIES {
+            break response;
+        }
+        attempt += 1;
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map_or(DEFAULT_BACKOFF, Duration::from_secs)
+            .min(MAX_BACKOFF);
+        tokio::time::sleep(retry_after).await;
+    };
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        Err(anyhow!(
+            "WallHaven is still rate limiting after {MAX_RETRIES} retries"
+        ))?;
+    }
+    if response.status() == StatusCode::UNAUTHORIZED {
+        Err(anyhow!("WallHaven rejected the API key"))?;
+    }
     if !response.status().is_success() {
         Err(anyhow!(
             "HTTP {} while attempting to communicate with WallHaven",
@@ -150,15 +394,295 @@ async fn wallpapers_page(
         .data
         .into_iter()
         .map(|datum| {
-            Wallpaper::new(
+            let wallpaper = Wallpaper::new(
                 datum.id.to_owned(),
                 Some(datum.id),
                 datum.path,
                 Some(datum.url),
                 source.to_string(),
             )
+            .with_file_size(datum.file_size as i64)
+            .with_colors(&datum.colors);
+            // Missing dimensions are read as 0, which would make every size filter reject it
+            if datum.dimension_x > 0 && datum.dimension_y > 0 {
+                wallpaper.with_dimensions(datum.dimension_x as i64, datum.dimension_y as i64)
+            } else {
+                wallpaper
+            }
         })
         .filter(|wallpaper| !ids.contains(&wallpaper.id))
         .collect();
-    Ok(wallpapers)
+    Ok((wallpapers, response.meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response_data::{Category, FileType, Purity};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    const SEARCH: &str = include_str!("../fixtures/search.json");
+    /// An empty first page of two, sorted randomly
+    const EMPTY: &str = include_str!("../fixtures/empty.json");
+
+    fn ok(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn rate_limited() -> String {
+        "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            .to_string()
+    }
+
+    /// Answers requests on a local port with `responses` in turn, repeating the last one.
+    /// Returns the base URL along with the request lines received
+    fn serve(responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/api/v1/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for (count, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Searches have no body, so the headers end the request
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(request_line.trim().to_string());
+                let response = &responses[count.min(responses.len() - 1)];
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (base_url, requests)
+    }
+
+    async fn fetch(parameters: Parameters) -> Result<Vec<Wallpaper>, Box<dyn Error>> {
+        let mut source = WallHavenSource {
+            instances: HashMap::new(),
+        };
+        source
+            .register_instance("test".to_string(), parameters)
+            .await?;
+        source.get_wallpapers("test".to_string(), Vec::new()).await
+    }
+
+    #[test]
+    fn default_query() {
+        let query = Parameters::default().search_query(1, None);
+        let expected = [
+            ("q", ""),
+            ("categories", "111"),
+            ("purity", "100"),
+            ("sorting", "date_added"),
+            ("order", "desc"),
+            ("page", "1"),
+        ];
+        assert_eq!(query, expected.map(|(key, value)| (key, value.to_string())));
+    }
+
+    #[test]
+    fn full_query() {
+        let parameters = Parameters {
+            tags: vec!["nature".to_string(), "-city".to_string()],
+            categories: Categories {
+                general: true,
+                anime: false,
+                people: false,
+            },
+            purity: Purities {
+                sfw: true,
+                sketchy: true,
+                nsfw: false,
+            },
+            sorting: Sorting::Toplist,
+            order: Order::Asc,
+            top_range: Some(TopRange::ThreeMonths),
+            atleast: Some("2560x1440".to_string()),
+            resolutions: vec!["3840x2160".to_string(), "2560x1440".to_string()],
+            ratios: vec!["16x9".to_string()],
+            colors: vec!["663399".to_string()],
+            ..Default::default()
+        };
+        let query = parameters
+            .search_query(4, Some("Xy12Ab"))
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        assert_eq!(query["q"], "nature -city");
+        assert_eq!(query["categories"], "100");
+        assert_eq!(query["purity"], "110");
+        assert_eq!(query["sorting"], "toplist");
+        assert_eq!(query["order"], "asc");
+        assert_eq!(query["page"], "4");
+        assert_eq!(query["topRange"], "3M");
+        assert_eq!(query["atleast"], "2560x1440");
+        assert_eq!(query["resolutions"], "3840x2160,2560x1440");
+        assert_eq!(query["ratios"], "16x9");
+        assert_eq!(query["colors"], "663399");
+        assert_eq!(query["seed"], "Xy12Ab");
+    }
+
+    #[test]
+    fn leaves_out_empty_filters() {
+        let query = Parameters::default().search_query(1, None);
+        for key in [
+            "topRange",
+            "atleast",
+            "resolutions",
+            "ratios",
+            "colors",
+            "seed",
+        ] {
+            assert!(query.iter().all(|(k, _)| *k != key), "{key} is set");
+        }
+    }
+
+    #[test]
+    fn deserializes_response() {
+        let response: BaseResponse = serde_json::from_str(SEARCH).unwrap();
+        assert_eq!(response.data.len(), 2);
+        let known = &response.data[0];
+        assert!(matches!(known.category, Category::General));
+        assert!(matches!(known.purity, Purity::Sfw));
+        assert!(matches!(known.file_type, FileType::ImageJpeg));
+        assert_eq!(
+            known.thumbs.as_ref().unwrap().original,
+            "https://th.wallhaven.cc/orig/85/85e3k1.jpg"
+        );
+        // Values added to the API since are tolerated
+        let unknown = &response.data[1];
+        assert!(matches!(unknown.category, Category::Unknown));
+        assert!(matches!(unknown.purity, Purity::Unknown));
+        assert!(matches!(unknown.file_type, FileType::Unknown));
+        assert_eq!(unknown.short_url, "");
+    }
+
+    #[test]
+    fn deserializes_meta() {
+        let meta = serde_json::from_str::<BaseResponse>(SEARCH).unwrap().meta;
+        // Counts come as strings for some queries
+        assert_eq!(meta.last_page, 3);
+        assert_eq!(meta.per_page, 24);
+        assert_eq!(meta.total, 50);
+        assert_eq!(meta.seed, None);
+        let meta = serde_json::from_str::<BaseResponse>(EMPTY).unwrap().meta;
+        assert_eq!(meta.last_page, 2);
+        assert_eq!(meta.seed.as_deref(), Some("Xy12Ab"));
+    }
+
+    #[tokio::test]
+    async fn converts_results() {
+        let (base_url, requests) = serve(vec![ok(SEARCH)]);
+        let parameters = Parameters {
+            base_url,
+            ..Default::default()
+        };
+        let (wallpapers, meta) = wallpapers_page(
+            "WallHaven_test",
+            &parameters,
+            &vec!["w8x2mq".to_string()],
+            1,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(meta.last_page, 3);
+        // The known one is left out
+        assert_eq!(wallpapers.len(), 1);
+        let wallpaper = &wallpapers[0];
+        assert_eq!(wallpaper.id, "85e3k1");
+        assert_eq!(
+            wallpaper.data_url,
+            "https://w.wallhaven.cc/full/85/wallhaven-85e3k1.jpg"
+        );
+        assert_eq!(
+            wallpaper.info_url.as_deref(),
+            Some("https://wallhaven.cc/w/85e3k1")
+        );
+        assert_eq!(
+            (wallpaper.width, wallpaper.height),
+            (Some(3840), Some(2160))
+        );
+        assert_eq!(wallpaper.file_size, Some(4211543));
+        assert_eq!(wallpaper.colors.as_deref(), Some("#424153,#000000"));
+        assert_eq!(wallpaper.source, "WallHaven_test");
+        assert!(requests.lock().unwrap()[0].starts_with("GET /api/v1/search?q=&"));
+    }
+
+    #[tokio::test]
+    async fn leaves_out_missing_dimensions() {
+        let body = r#"{
+            "data": [{"id": "x1", "url": "https://wallhaven.cc/w/x1", "path": "https://w.wallhaven.cc/full/x1/wallhaven-x1.png", "dimension_x": 1920}],
+            "meta": {"current_page": 1, "last_page": 1, "per_page": 24, "total": 1}
+        }"#;
+        let (base_url, _) = serve(vec![ok(body)]);
+        let parameters = Parameters {
+            base_url,
+            ..Default::default()
+        };
+        let (wallpapers, _) = wallpapers_page("WallHaven_test", &parameters, &vec![], 1, None)
+            .await
+            .unwrap();
+        assert_eq!((wallpapers[0].width, wallpapers[0].height), (None, None));
+    }
+
+    #[tokio::test]
+    async fn stops_at_last_page() {
+        let (base_url, requests) = serve(vec![ok(EMPTY)]);
+        let wallpapers = fetch(Parameters {
+            base_url,
+            sorting: Sorting::Random,
+            max_pages: 5,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(wallpapers.is_empty());
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].contains("&page=1"));
+        assert!(!requests[0].contains("seed="));
+        // The seed of the first page is passed back
+        assert!(requests[1].contains("&page=2"));
+        assert!(requests[1].contains("&seed=Xy12Ab"));
+    }
+
+    #[tokio::test]
+    async fn retries_when_rate_limited() {
+        let (base_url, requests) = serve(vec![rate_limited(), rate_limited(), ok(SEARCH)]);
+        let wallpapers = fetch(Parameters {
+            base_url,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(wallpapers.len(), 2);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_still_rate_limited() {
+        let (base_url, requests) = serve(vec![rate_limited()]);
+        let result = fetch(Parameters {
+            base_url,
+            ..Default::default()
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(requests.lock().unwrap().len(), MAX_RETRIES as usize + 1);
+    }
 }
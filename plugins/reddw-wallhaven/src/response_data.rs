@@ -1,4 +1,7 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Everything besides what's needed to locate a wallpaper is defaulted, and unknown enum values
+// are tolerated, so that additions to the API don't break the plugin
 
 #[derive(Serialize, Deserialize)]
 pub struct BaseResponse {
@@ -10,43 +13,71 @@ pub struct BaseResponse {
 pub struct Datum {
     pub id: String,
     pub url: String,
+    #[serde(default)]
     pub short_url: String,
+    #[serde(default)]
     pub views: u64,
+    #[serde(default)]
     pub favorites: u64,
+    #[serde(default)]
     pub source: String,
+    #[serde(default)]
     pub purity: Purity,
+    #[serde(default)]
     pub category: Category,
+    #[serde(default)]
     pub dimension_x: u64,
+    #[serde(default)]
     pub dimension_y: u64,
+    #[serde(default)]
     pub resolution: String,
+    #[serde(default)]
     pub ratio: String,
+    #[serde(default)]
     pub file_size: u64,
+    #[serde(default)]
     pub file_type: FileType,
+    #[serde(default)]
     pub created_at: String,
+    #[serde(default)]
     pub colors: Vec<String>,
     pub path: String,
     pub thumbs: Option<Thumbs>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum Category {
     Anime,
     General,
+    People,
+    #[default]
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub enum FileType {
     #[serde(rename = "image/jpeg")]
     ImageJpeg,
     #[serde(rename = "image/png")]
     ImagePng,
+    #[serde(rename = "image/webp")]
+    ImageWebp,
+    #[default]
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum Purity {
     Sfw,
+    Sketchy,
+    Nsfw,
+    #[default]
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -58,10 +89,31 @@ pub struct Thumbs {
 
 #[derive(Serialize, Deserialize)]
 pub struct Meta {
+    #[serde(deserialize_with = "number_or_string")]
     pub current_page: u64,
+    #[serde(deserialize_with = "number_or_string")]
     pub last_page: u64,
+    /// WallHaven sends this as a string for some queries
+    #[serde(deserialize_with = "number_or_string")]
     pub per_page: u64,
+    #[serde(deserialize_with = "number_or_string")]
     pub total: u64,
+    #[serde(default)]
     query: Option<serde_json::Value>,
-    seed: Option<serde_json::Value>,
+    /// Set when sorting randomly, and needs to be passed back to get consistent pages
+    #[serde(default)]
+    pub seed: Option<String>,
+}
+
+fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(u64),
+        String(String),
+    }
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(number) => Ok(number),
+        NumberOrString::String(string) => string.parse().map_err(serde::de::Error::custom),
+    }
 }
@@ -3,7 +3,19 @@ import { AiOutlineCloseCircle } from "solid-icons/ai";
 import { JSX } from "solid-js/jsx-runtime";
 
 export type PluginConfig = {
-    tags: string[]
+    tags: string[],
+    categories?: { general: boolean, anime: boolean, people: boolean },
+    purity?: { sfw: boolean, sketchy: boolean, nsfw: boolean },
+    sorting?: "dateAdded" | "relevance" | "random" | "views" | "favorites" | "toplist",
+    order?: "desc" | "asc",
+    topRange?: "day" | "threeDays" | "week" | "month" | "threeMonths" | "sixMonths" | "year",
+    atleast?: string,
+    resolutions?: string[],
+    ratios?: string[],
+    colors?: string[],
+    apiKey?: string,
+    baseUrl?: string,
+    maxPages?: number,
 };
 export type ComponentEventHandler = (event: CustomEvent<PluginConfig>) => (unknown | Promise<unknown>);
 
@@ -0,0 +1,5 @@
+CREATE TABLE "favorites" (
+	"id"	TEXT NOT NULL,
+	"date"	DATETIME NOT NULL,
+	PRIMARY KEY("id")
+);
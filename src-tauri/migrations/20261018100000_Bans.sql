@@ -0,0 +1,7 @@
+CREATE TABLE "bans" (
+	"id"	TEXT NOT NULL,
+	"source"	TEXT NOT NULL,
+	"data_url"	TEXT NOT NULL,
+	"date"	DATETIME NOT NULL,
+	PRIMARY KEY("id")
+);
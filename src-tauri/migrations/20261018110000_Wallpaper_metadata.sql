@@ -0,0 +1,11 @@
+alter table "queue" add column "width" integer default null;
+
+alter table "queue" add column "height" integer default null;
+
+alter table "queue" add column "file_size" integer default null;
+
+alter table "queue" add column "author" text default null;
+
+alter table "queue" add column "tags" text default null;
+
+alter table "queue" add column "colors" text default null;
@@ -0,0 +1,8 @@
+CREATE TABLE "rejections" (
+	"id"	TEXT NOT NULL,
+	"source"	TEXT NOT NULL,
+	"data_url"	TEXT NOT NULL,
+	"reason"	TEXT NOT NULL,
+	"date"	DATETIME NOT NULL,
+	PRIMARY KEY("id")
+);
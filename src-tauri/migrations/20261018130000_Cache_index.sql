@@ -0,0 +1,10 @@
+CREATE TABLE "cache_index" (
+	"path"	TEXT NOT NULL,
+	"id"	TEXT NOT NULL,
+	"size"	INTEGER NOT NULL,
+	"accessed"	DATETIME NOT NULL,
+	"pinned"	BOOLEAN NOT NULL DEFAULT 0,
+	PRIMARY KEY("path")
+);
+
+CREATE INDEX "cache_index_id" ON "cache_index" ("id");
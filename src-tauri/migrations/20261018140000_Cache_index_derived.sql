@@ -0,0 +1 @@
+alter table "cache_index" add column "derived" boolean not null default 0;
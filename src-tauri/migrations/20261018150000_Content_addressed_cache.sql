@@ -0,0 +1,23 @@
+-- Cached files are now named and indexed by their content's hash.
+-- Files cached under the old names are picked up again by verifying the cache
+drop table "cache_index";
+
+CREATE TABLE "cache_index" (
+	"path"	TEXT NOT NULL,
+	"hash"	TEXT NOT NULL,
+	"size"	INTEGER NOT NULL,
+	"accessed"	DATETIME NOT NULL,
+	"pinned"	BOOLEAN NOT NULL DEFAULT 0,
+	"derived"	BOOLEAN NOT NULL DEFAULT 0,
+	PRIMARY KEY("path")
+);
+
+CREATE INDEX "cache_index_hash" ON "cache_index" ("hash");
+
+CREATE TABLE "contents" (
+	"id"	TEXT NOT NULL,
+	"hash"	TEXT NOT NULL,
+	PRIMARY KEY("id")
+);
+
+CREATE INDEX "contents_hash" ON "contents" ("hash");
@@ -0,0 +1,6 @@
+-- Wallpapers shown on an output other than the first. They're marked as set so they aren't
+-- picked again, but aren't history entries of their own
+CREATE TABLE "retired" (
+	"id"	TEXT NOT NULL,
+	PRIMARY KEY("id")
+);
@@ -1,7 +1,14 @@
 use crate::{
     app_handle_ext::AppHandleExt,
+    displays::DisplayConfig,
+    effects::Effect,
+    filters::ImageFilter,
     log::{LogBehaviours, LogLevel},
+    lookahead::refresh_lookahead,
+    processing::ScalingMode,
+    retry::RetryPolicy,
     // queue::manage_queue,
+    schedule::{Rotation, Schedule},
     source_host::{PluginHostMode, SourcePlugins},
     watcher::watch_path_sync,
 };
@@ -15,7 +22,10 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::query;
 use std::{
-    collections::HashMap, fs::{self, read_to_string}, path::{Path, PathBuf}, time::Duration
+    collections::HashMap,
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 use tauri::{
     async_runtime::{spawn, Mutex, Sender},
@@ -31,28 +41,80 @@ pub struct AppConfig {
     pub display_background: bool,
     #[ts(type = "Record<string, any>")]
     pub sources: HashMap<String, Value>,
+    /// Relative chance of each source being picked, keyed like `sources`. Missing entries weigh 1
+    #[serde(default)]
+    pub source_weights: HashMap<String, f64>,
+    #[serde(default)]
+    pub source_selection: SourceSelection,
     #[ts(type = "{secs: number, nanos: number}")]
     /// How often to switch new wallpapers (in seconds)
     pub interval: Duration,
+    /// Rules for when to switch wallpapers. Overrides `interval` unless empty
+    #[serde(default)]
+    pub schedule: Schedule,
     pub cache_dir: PathBuf,
     pub plugin_host_mode: PluginHostMode,
     // Max cache size, in megabytes
     pub cache_size: f64,
+    /// Largest image that will be downloaded, in megabytes
+    #[serde(default = "default_max_download_size")]
+    pub max_download_size: f64,
+    /// How many wallpapers are downloaded at once when filling the cache
+    #[serde(default = "default_prefetch_concurrency")]
+    pub prefetch_concurrency: usize,
+    /// How failed downloads, plugin calls and scheduled changes are retried
+    #[serde(default)]
+    pub retry: RetryPolicy,
     pub plugins_dir: Option<PathBuf>,
     pub history_amount: i32,
     pub theme: String,
     #[ts(skip)]
     pub logging: LogBehaviours,
     pub setter_command: Option<String>,
+    /// Only rotate through favorited wallpapers
+    #[serde(default)]
+    pub favorites_only: bool,
+    /// Per-output overrides, keyed by output name. Only used alongside a setter command
+    #[serde(default)]
+    pub displays: HashMap<String, DisplayConfig>,
+    /// Dimensions wallpapers need, for sources without an entry in `source_filters`
+    #[serde(default)]
+    pub filter: ImageFilter,
+    /// Per-source filters, keyed like `sources`. These replace the global filter
+    #[serde(default)]
+    pub source_filters: HashMap<String, ImageFilter>,
+    /// How wallpapers are fitted to the screen before being set
+    #[serde(default)]
+    pub scaling: ScalingMode,
+    /// Hex colour shown around wallpapers which don't cover the screen. Black if unset
+    #[serde(default)]
+    pub background_color: Option<String>,
+    /// Applied in order after scaling
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+}
+
+fn default_max_download_size() -> f64 {
+    50.0
+}
+
+fn default_prefetch_concurrency() -> usize {
+    3
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             sources: HashMap::new(),
+            source_weights: HashMap::new(),
+            source_selection: SourceSelection::default(),
             interval: Duration::from_secs(60 * 60),
+            schedule: Schedule::new(),
             cache_dir: PathBuf::new(),
             cache_size: 100.0,
+            max_download_size: default_max_download_size(),
+            prefetch_concurrency: default_prefetch_concurrency(),
+            retry: RetryPolicy::default(),
             history_amount: 10,
             plugins_dir: None,
             plugin_host_mode: PluginHostMode::Daemon,
@@ -60,11 +122,49 @@ impl Default for AppConfig {
             display_background: true,
             logging: LogBehaviours::new(),
             setter_command: None,
+            favorites_only: false,
+            displays: HashMap::new(),
+            filter: ImageFilter::default(),
+            source_filters: HashMap::new(),
+            scaling: ScalingMode::default(),
+            background_color: None,
+            effects: Vec::new(),
         }
     }
 }
 
-pub fn build(app: AppHandle, tx_interval: Sender<Duration>) -> tauri::Result<()> {
+impl AppConfig {
+    /// Relative chance of each of `sources` being picked.
+    /// `queued` holds how many wallpapers each source has waiting, for `SourceSelection::QueueSize`
+    pub fn source_weights(&self, sources: &[&String], queued: &HashMap<String, i64>) -> Vec<f64> {
+        sources
+            .iter()
+            .map(|source| {
+                let weight = self.source_weights.get(*source).copied().unwrap_or(1.0);
+                match self.source_selection {
+                    SourceSelection::Weighted => weight,
+                    // Empty queues still need a chance to be picked, or they'd never get refilled
+                    SourceSelection::QueueSize => {
+                        weight * queued.get(*source).copied().unwrap_or(0).max(1) as f64
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// How a source is picked for the next wallpaper
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub enum SourceSelection {
+    /// Sources are picked in proportion to their weights
+    #[default]
+    Weighted,
+    /// Weights are further multiplied by how many wallpapers each source has queued
+    QueueSize,
+}
+
+pub fn build(app: AppHandle, tx_rotation: Sender<Rotation>) -> tauri::Result<()> {
     let config_dir = app.path_resolver().app_config_dir().unwrap();
     if !&config_dir.exists() {
         fs::create_dir_all(&config_dir)?;
@@ -93,10 +193,13 @@ pub fn build(app: AppHandle, tx_interval: Sender<Duration>) -> tauri::Result<()>
             fs::write(&config_path, def_conf_str).expect("Failed to write default config");
             def_conf
         });
-        tx_interval
-            .try_send(config.interval)
+        tx_rotation
+            .try_send(Rotation {
+                interval: config.interval,
+                schedule: config.schedule.clone(),
+            })
             .or(Err(tauri::Error::FailedToSendMessage))?;
-        app.manage(tx_interval);
+        app.manage(tx_rotation);
         app.manage(Mutex::new(config.clone()));
     }
 
@@ -164,8 +267,20 @@ pub fn build(app: AppHandle, tx_interval: Sender<Duration>) -> tauri::Result<()>
                     )
                     .await?;
                 }
+
+                if old_config.schedule != config.schedule {
+                    config = update_config(
+                        app.app_handle(),
+                        ConfigUpdate::ChangeSchedule {
+                            schedule: config.schedule.clone(),
+                        },
+                    )
+                    .await?;
+                }
                 app.emit_all("config_changed", &config)?;
                 *app.state::<Mutex<AppConfig>>().lock().await = config;
+                // Prepared wallpapers may not fit the new config
+                refresh_lookahead(app.app_handle());
                 Ok(())
             })()
             .unwrap_or_else(|err: anyhow::Error| app_clone.log(&err, LogLevel::Error))
@@ -186,6 +301,7 @@ pub async fn update_config(app: AppHandle, update: ConfigUpdate) -> Result<AppCo
         ConfigUpdate::Other(new_config) => AppConfig {
             sources: current_config.sources,
             interval: current_config.interval,
+            schedule: current_config.schedule,
             ..new_config
         },
         ConfigUpdate::AddSource(plugin_instance, params) => {
@@ -202,9 +318,12 @@ pub async fn update_config(app: AppHandle, update: ConfigUpdate) -> Result<AppCo
                     .map_err(|err| anyhow!("{err:#?}"))?
             };
             if overrode {
-                query!("delete from queue where source = ?", plugin_instance)
-                    .execute(&app.db().await)
-                    .await?;
+                query!(
+                    "delete from queue where source = ? and id not in (select id from favorites)",
+                    plugin_instance
+                )
+                .execute(&app.db().await)
+                .await?;
             }
             let mut sources = current_config.sources;
             sources.insert(
@@ -227,24 +346,51 @@ pub async fn update_config(app: AppHandle, update: ConfigUpdate) -> Result<AppCo
                 .deregister_instance(instance.to_string())
                 .await
                 .map_err(|err| anyhow!("{err:#?}"))?;
-            query!("delete from queue where source = ?", plugin_instance)
-                .execute(&app.db().await)
-                .await?;
+            query!(
+                "delete from queue where source = ? and id not in (select id from favorites)",
+                plugin_instance
+            )
+            .execute(&app.db().await)
+            .await?;
             let mut sources = current_config.sources;
             sources.remove(&plugin_instance);
+            let mut source_weights = current_config.source_weights;
+            source_weights.remove(&plugin_instance);
+            let mut source_filters = current_config.source_filters;
+            source_filters.remove(&plugin_instance);
             AppConfig {
                 sources,
+                source_weights,
+                source_filters,
                 ..current_config
             }
         }
         ConfigUpdate::ChangeInterval { interval } => {
-            let tx_interval = app.state::<Sender<Duration>>();
-            tx_interval.send(interval).await?;
+            let tx_rotation = app.state::<Sender<Rotation>>();
+            tx_rotation
+                .send(Rotation {
+                    interval,
+                    schedule: current_config.schedule.clone(),
+                })
+                .await?;
             AppConfig {
                 interval,
                 ..current_config
             }
         }
+        ConfigUpdate::ChangeSchedule { schedule } => {
+            let tx_rotation = app.state::<Sender<Rotation>>();
+            tx_rotation
+                .send(Rotation {
+                    interval: current_config.interval,
+                    schedule: schedule.clone(),
+                })
+                .await?;
+            AppConfig {
+                schedule,
+                ..current_config
+            }
+        }
     };
     Ok(updated_config)
 }
@@ -304,6 +450,61 @@ pub enum ConfigUpdate {
         #[ts(type = "{secs: number, nanos: number}")]
         interval: Duration,
     },
+    ChangeSchedule {
+        schedule: Schedule,
+    },
     /// Config changes which do not require special behaviour on the backend
     Other(AppConfig),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::{Distribution, WeightedIndex};
+
+    fn config(weights: &[(&str, f64)], source_selection: SourceSelection) -> AppConfig {
+        AppConfig {
+            source_weights: weights
+                .iter()
+                .map(|(source, weight)| (source.to_string(), *weight))
+                .collect(),
+            source_selection,
+            ..Default::default()
+        }
+    }
+
+    fn keys(sources: &[&str]) -> Vec<String> {
+        sources.iter().map(|source| source.to_string()).collect()
+    }
+
+    #[test]
+    fn missing_weights_are_one() {
+        let sources = keys(&["Local_a", "Feed_b", "Urls_c"]);
+        let sources = sources.iter().collect::<Vec<_>>();
+        let config = config(&[("Feed_b", 2.5)], SourceSelection::Weighted);
+        assert_eq!(
+            config.source_weights(&sources, &HashMap::new()),
+            [1.0, 2.5, 1.0]
+        );
+    }
+
+    #[test]
+    fn zero_weight_is_never_picked() {
+        let sources = keys(&["Local_a", "Feed_b"]);
+        let sources = sources.iter().collect::<Vec<_>>();
+        let config = config(&[("Local_a", 0.0)], SourceSelection::Weighted);
+        let index = WeightedIndex::new(config.source_weights(&sources, &HashMap::new())).unwrap();
+        let mut rng = rand::thread_rng();
+        assert!((0..1000).all(|_| index.sample(&mut rng) == 1));
+    }
+
+    #[test]
+    fn queue_size_scales_weights() {
+        let sources = keys(&["Local_a", "Feed_b", "Urls_c"]);
+        let sources = sources.iter().collect::<Vec<_>>();
+        let config = config(&[("Local_a", 0.5)], SourceSelection::QueueSize);
+        let queued = HashMap::from([("Local_a".to_string(), 10), ("Feed_b".to_string(), 3)]);
+        // Sources with nothing queued count as having one
+        assert_eq!(config.source_weights(&sources, &queued), [5.0, 3.0, 1.0]);
+    }
+}
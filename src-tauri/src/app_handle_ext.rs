@@ -6,6 +6,7 @@ use std::{
 use crate::{
     app_config::AppConfig,
     log::{log as log_func, LogLevel},
+    pause::PauseState,
     queue::DB,
 };
 use anyhow::Result;
@@ -19,6 +20,7 @@ pub trait AppHandleExt {
     fn get_config_path(&self) -> PathBuf;
     async fn get_config(&self) -> AppConfig;
     async fn db(&self) -> DB;
+    async fn pause_state(&self) -> PauseState;
     fn log(&self, message: &dyn Display, level: LogLevel) -> ();
     async fn listen_ipc<T: for<'a> Deserialize<'a>>(
         &self,
@@ -37,6 +39,10 @@ impl AppHandleExt for AppHandle {
     async fn db(&self) -> DB {
         self.state::<Mutex<DB>>().lock().await.clone()
     }
+    async fn pause_state(&self) -> PauseState {
+        let state = self.state::<Mutex<PauseState>>().lock().await.clone();
+        state.at(chrono::Utc::now())
+    }
 
     fn log(&self, message: &dyn Display, level: LogLevel) -> () {
         log_func(self, message, level)
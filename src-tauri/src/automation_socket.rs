@@ -1,13 +1,21 @@
 #[cfg(target_family = "unix")]
 use crate::app_handle_ext::AppHandleExt;
-use crate::{main_window_setup, wallpaper_changer::update_wallpaper};
+use crate::{
+    bans::ban_current,
+    cache::verify_cache,
+    history::{next_wallpaper, previous_wallpaper},
+    main_window_setup,
+    pause::{pause, pause_until, resume, snooze_current},
+    wallpaper_changer::update_wallpaper,
+};
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use reddw_ipc::{IPCData, IPCMessage, SOCKET_PATH};
 use serde::{Deserialize, Serialize};
 use serde_cbor::{from_slice, to_vec};
 #[cfg(target_family = "unix")]
 use std::fs::remove_file;
-use std::{io::ErrorKind, process::exit};
+use std::{io::ErrorKind, process::exit, time::Duration};
 use tauri::{async_runtime::spawn, AppHandle, Manager};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -27,14 +35,25 @@ pub struct Args {
     pub update: bool,
     #[arg(short, long)]
     pub fetch: bool,
+    /// Reconcile the cache directory with the database
+    #[arg(long)]
+    pub verify_cache: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     UpdateWallpaper,
+    PreviousWallpaper,
+    NextWallpaper,
     UpdateFromSource(String),
     Show,
     FetchCache,
+    Pause,
+    PauseUntil(DateTime<Utc>),
+    Snooze(Duration),
+    Resume,
+    BanCurrent,
+    VerifyCache,
     Quit,
 }
 
@@ -47,6 +66,14 @@ pub async fn handle_automation(app: AppHandle, message: Message) -> Result<()> {
             Ok(())
         }
         Message::UpdateWallpaper => update_wallpaper(app.app_handle()).await,
+        Message::PreviousWallpaper => previous_wallpaper(app.app_handle()).await,
+        Message::NextWallpaper => next_wallpaper(app.app_handle()).await,
+        Message::Pause => pause(app.app_handle()).await,
+        Message::PauseUntil(until) => pause_until(app.app_handle(), until).await,
+        Message::Snooze(duration) => snooze_current(app.app_handle(), duration).await,
+        Message::Resume => resume(app.app_handle()).await,
+        Message::BanCurrent => ban_current(app.app_handle()).await,
+        Message::VerifyCache => verify_cache(app.app_handle()).await.map(|_| ()),
         // Message::UpdateFromSource(source) => {
         //     update_wallpaper(app.handle()).await?;
         //     set_config(app.handle(), source).await?;
@@ -86,6 +113,8 @@ pub async fn initiate_ipc(args: &Args, app: AppHandle) -> Result<()> {
                                 Message::Quit
                             } else if args.fetch {
                                 Message::FetchCache
+                            } else if args.verify_cache {
+                                Message::VerifyCache
                             } else if args.update {
                                 Message::UpdateWallpaper
                             } else {
@@ -146,6 +175,8 @@ pub async fn initiate_ipc(args: &Args, app: AppHandle) -> Result<()> {
                                 Message::Quit
                             } else if args.fetch {
                                 Message::FetchCache
+                            } else if args.verify_cache {
+                                Message::VerifyCache
                             } else if args.update {
                                 Message::UpdateWallpaper
                             } else {
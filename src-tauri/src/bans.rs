@@ -0,0 +1,69 @@
+use crate::{
+    app_handle_ext::AppHandleExt, cache, queue::current_wallpaper,
+    wallpaper_changer::update_wallpaper,
+};
+use anyhow::{anyhow, Result};
+use macros::command;
+use reddw_source_plugin::Wallpaper;
+use sqlx::{query, query_as};
+use tauri::{AppHandle, Manager};
+
+/// Bans a wallpaper so no source offers it again, dropping it from the queue and the cache.
+/// Banning the current wallpaper replaces it straight away
+pub async fn ban_wallpaper(app: &AppHandle, id: &str) -> Result<()> {
+    let db = app.db().await;
+    let wallpaper = query_as!(Wallpaper, "select * from queue where id = ?", id)
+        .fetch_optional(&db)
+        .await?
+        .ok_or(anyhow!("No wallpaper with the ID {id}"))?;
+    let is_current = current_wallpaper(app)
+        .await?
+        .is_some_and(|current| current.id == wallpaper.id);
+    let now = chrono::Utc::now().naive_utc();
+    query!(
+        "insert or replace into bans (id, source, data_url, date) values ($1, $2, $3, $4)",
+        wallpaper.id,
+        wallpaper.source,
+        wallpaper.data_url,
+        now
+    )
+    .execute(&db)
+    .await?;
+    query!("delete from favorites where id = ?", wallpaper.id)
+        .execute(&db)
+        .await?;
+    query!("delete from queue where id = ?", wallpaper.id)
+        .execute(&db)
+        .await?;
+
+    cache::remove_files(app, &wallpaper.id).await?;
+    app.emit_all("wallpaper_banned", &wallpaper)?;
+
+    if is_current {
+        update_wallpaper(app.app_handle())
+            .await
+            .map_err(|err| anyhow!(err))?;
+    }
+    Ok(())
+}
+
+#[command]
+pub async fn ban(app: AppHandle, id: String) -> Result<()> {
+    ban_wallpaper(&app, &id).await
+}
+
+#[command]
+pub async fn unban(app: AppHandle, id: String) -> Result<()> {
+    query!("delete from bans where id = ?", id)
+        .execute(&app.db().await)
+        .await?;
+    Ok(())
+}
+
+#[command]
+pub async fn ban_current(app: AppHandle) -> Result<()> {
+    let wallpaper = current_wallpaper(&app)
+        .await?
+        .ok_or(anyhow!("No wallpaper is set"))?;
+    ban_wallpaper(&app, &wallpaper.id).await
+}
@@ -0,0 +1,516 @@
+use crate::{
+    app_handle_ext::AppHandleExt,
+    log::LogLevel,
+    queue::{current_wallpaper, DB},
+    wallpaper_changer::{download_wallpaper, hash_url},
+};
+use anyhow::Result;
+use macros::command;
+use mime_guess::mime::IMAGE;
+use reddw_source_plugin::Wallpaper;
+use serde::Serialize;
+use sqlx::{query, query_as};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tauri::{async_runtime::RwLock, AppHandle, Manager};
+use ts_rs::TS;
+
+// Cached files are named after the SHA-256 of their content, so wallpapers which turn out to be
+// the same image share a file. `contents` maps wallpaper IDs to those hashes
+
+/// Extension of files which are still being downloaded
+pub const PARTIAL_EXTENSION: &str = "part";
+/// How long a partial download has to go untouched before it's considered abandoned
+const STALE_PARTIAL: Duration = Duration::from_secs(60 * 60);
+
+/// Held for writing while `verify` goes over the cache directory, and for reading while a
+/// download is moved into it and recorded, so a fresh download is never taken for an orphan
+pub type CacheLock = RwLock<()>;
+
+pub fn setup_cache(app: AppHandle) -> bool {
+    app.manage(CacheLock::default())
+}
+
+/// The configured cache size, in bytes
+pub async fn cache_limit(app: &AppHandle) -> u64 {
+    (app.get_config().await.cache_size * 1024.0 * 1024.0).floor() as u64
+}
+
+/// Total size of the cached images
+pub async fn cached_bytes(app: &AppHandle) -> Result<u64> {
+    let total = query!(r#"select coalesce(sum(size), 0) as "total!: i64" from cache_index"#)
+        .fetch_one(&app.db().await)
+        .await?
+        .total;
+    Ok(total as u64)
+}
+
+/// The content hash a cached file is named after.
+/// Processed copies are named after the original, with the processing in between
+pub fn file_hash(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    let (hash, _) = name.split_once('.')?;
+    Some(hash.to_string())
+}
+
+/// Remembers which content a wallpaper turned out to have
+pub async fn record_content(app: &AppHandle, id: &str, hash: &str) -> Result<()> {
+    query!(
+        "insert or replace into contents (id, hash) values ($1, $2)",
+        id,
+        hash
+    )
+    .execute(&app.db().await)
+    .await?;
+    Ok(())
+}
+
+/// Adds a file to the index, or refreshes its entry.
+/// Derived files are processed copies of an image, rather than the download itself
+pub async fn record_file(app: &AppHandle, hash: &str, path: &Path, derived: bool) -> Result<()> {
+    let size = fs::metadata(path)?.len() as i64;
+    let path = path.to_string_lossy();
+    let now = chrono::Utc::now().naive_utc();
+    query!(
+        "---sql
+        insert into cache_index (path, hash, size, accessed, derived) values ($1, $2, $3, $4, $5)
+        on conflict (path) do update set hash = $2, size = $3, accessed = $4, derived = $5",
+        path,
+        hash,
+        size,
+        now,
+        derived
+    )
+    .execute(&app.db().await)
+    .await?;
+    Ok(())
+}
+
+/// The downloaded file with the given content, if it's in the cache
+pub async fn content_file(app: &AppHandle, hash: &str) -> Result<Option<PathBuf>> {
+    let db = app.db().await;
+    let files = query!(
+        "select path from cache_index where hash = ? and derived = 0",
+        hash
+    )
+    .fetch_all(&db)
+    .await?;
+    for file in files {
+        let path = PathBuf::from(&file.path);
+        if path.exists() {
+            return Ok(Some(path));
+        }
+        query!("delete from cache_index where path = ?", file.path)
+            .execute(&db)
+            .await?;
+    }
+    Ok(None)
+}
+
+/// The downloaded file of a wallpaper, if it's in the cache
+pub async fn cached_file(app: &AppHandle, id: &str) -> Result<Option<PathBuf>> {
+    let content = query!("select hash from contents where id = ?", id)
+        .fetch_optional(&app.db().await)
+        .await?;
+    match content {
+        Some(content) => content_file(app, &content.hash).await,
+        None => Ok(None),
+    }
+}
+
+/// Marks a cached file as used, moving it to the back of the eviction order
+pub async fn touch_file(app: &AppHandle, path: &Path) -> Result<()> {
+    let path = path.to_string_lossy();
+    let now = chrono::Utc::now().naive_utc();
+    query!(
+        "update cache_index set accessed = $1 where path = $2",
+        now,
+        path
+    )
+    .execute(&app.db().await)
+    .await?;
+    Ok(())
+}
+
+/// Deletes cached files of the given content, along with their index entries
+async fn remove_content(app: &AppHandle, hash: &str) -> Result<()> {
+    let db = app.db().await;
+    let files = query!("select path from cache_index where hash = ?", hash)
+        .fetch_all(&db)
+        .await?;
+    for file in files {
+        if let Err(err) = fs::remove_file(&file.path)
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            Err(err)?;
+        }
+    }
+    query!("delete from cache_index where hash = ?", hash)
+        .execute(&db)
+        .await?;
+    Ok(())
+}
+
+/// Forgets a wallpaper's content, removing its files unless another wallpaper shares them
+pub async fn remove_files(app: &AppHandle, id: &str) -> Result<()> {
+    let db = app.db().await;
+    let Some(content) = query!("select hash from contents where id = ?", id)
+        .fetch_optional(&db)
+        .await?
+    else {
+        return Ok(());
+    };
+    query!("delete from contents where id = ?", id)
+        .execute(&db)
+        .await?;
+    let shared = query!("select id from contents where hash = ?", content.hash)
+        .fetch_optional(&db)
+        .await?
+        .is_some();
+    if !shared {
+        remove_content(app, &content.hash).await?;
+    }
+    Ok(())
+}
+
+/// Files which may be evicted, with their sizes, least recently used first.
+/// Pinned files and ones holding a favorite or the `current` wallpaper aren't
+async fn eviction_candidates(db: &DB, current: Option<&str>) -> Result<Vec<(String, i64)>> {
+    let candidates = query!(
+        "---sql
+        select path, size from cache_index
+        where pinned = 0
+            and hash not in (
+                select hash from contents
+                where id in (select id from favorites) or id is $1
+            )
+        order by accessed asc",
+        current
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|rec| (rec.path, rec.size))
+    .collect();
+    Ok(candidates)
+}
+
+/// Evicts the least recently used files until `incoming` more bytes fit within the cache size.
+/// Pinned files, favorites and the `current` wallpaper are never evicted, so the cache may
+/// end up over its size when they take up all of it.
+/// The current wallpaper is passed in, as this runs while the history cursor may be held
+pub async fn make_room(app: &AppHandle, incoming: u64, current: Option<&str>) -> Result<()> {
+    let limit = cache_limit(app).await;
+    let mut total = cached_bytes(app).await?;
+    if total + incoming <= limit {
+        return Ok(());
+    }
+    let db = app.db().await;
+    for (candidate, size) in eviction_candidates(&db, current).await? {
+        if total + incoming <= limit {
+            break;
+        }
+        let path = PathBuf::from(&candidate);
+        if let Err(err) = fs::remove_file(&path)
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            app.log(
+                &format!("Couldn't evict {}: {err}", path.display()),
+                LogLevel::Error,
+            );
+            continue;
+        }
+        query!("delete from cache_index where path = ?", candidate)
+            .execute(&db)
+            .await?;
+        total = total.saturating_sub(size as u64);
+    }
+    if total + incoming > limit {
+        app.log(
+            &"The cache is over its size, but everything left in it is pinned",
+            LogLevel::Debug,
+        );
+    }
+    Ok(())
+}
+
+async fn set_pinned(app: &AppHandle, id: &str, pinned: bool) -> Result<()> {
+    query!(
+        "update cache_index set pinned = $1 where hash = (select hash from contents where id = $2)",
+        pinned,
+        id
+    )
+    .execute(&app.db().await)
+    .await?;
+    Ok(())
+}
+
+/// Keeps a wallpaper's files from being evicted
+#[command]
+pub async fn pin(app: AppHandle, id: String) -> Result<()> {
+    set_pinned(&app, &id, true).await
+}
+
+#[command]
+pub async fn unpin(app: AppHandle, id: String) -> Result<()> {
+    set_pinned(&app, &id, false).await
+}
+
+/// What `verify_cache` changed
+#[derive(Serialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct CacheReport {
+    /// Files no wallpaper in the queue refers to, which were deleted
+    pub removed_orphans: Vec<String>,
+    /// Files which weren't in the index, but belong to a queued wallpaper
+    pub adopted: Vec<String>,
+    /// Missing files of favorites and the current wallpaper, which were downloaded again
+    pub redownloaded: Vec<String>,
+    /// Index entries for missing files, which were dropped
+    pub dropped: Vec<String>,
+    #[ts(type = "number")]
+    pub freed_bytes: u64,
+}
+
+impl Display for CacheReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cache verified: {} orphans removed ({} MB freed), {} files adopted, {} re-downloaded, {} missing entries dropped",
+            self.removed_orphans.len(),
+            self.freed_bytes / 1024 / 1024,
+            self.adopted.len(),
+            self.redownloaded.len(),
+            self.dropped.len()
+        )
+    }
+}
+
+/// Brings the cache directory and the database back in line with each other.
+/// Files of the `current` wallpaper are downloaded again if they went missing
+pub async fn verify(app: &AppHandle, current: Option<&str>) -> Result<CacheReport> {
+    let lock = app.state::<CacheLock>();
+    let scanning = lock.write().await;
+    let mut report = CacheReport::default();
+    let db = app.db().await;
+    let cache_dir = app.get_config().await.cache_dir;
+    let queued = query_as!(Wallpaper, "select * from queue")
+        .fetch_all(&db)
+        .await?;
+    let favorites = query!("select id from favorites")
+        .fetch_all(&db)
+        .await?
+        .into_iter()
+        .map(|rec| rec.id)
+        .collect::<HashSet<_>>();
+
+    // Contents of wallpapers which have left the queue
+    query!("delete from contents where id not in (select id from queue)")
+        .execute(&db)
+        .await?;
+    let contents = query!("select id, hash from contents")
+        .fetch_all(&db)
+        .await?
+        .into_iter()
+        .map(|rec| (rec.id, rec.hash))
+        .collect::<HashMap<_, _>>();
+    let hashes = contents.values().cloned().collect::<HashSet<_>>();
+
+    // Index entries no queued wallpaper has the content of, or whose file is gone from the disk
+    let indexed = query!("select path, hash, size, derived from cache_index")
+        .fetch_all(&db)
+        .await?;
+    let mut missing = HashSet::new();
+    for entry in &indexed {
+        let path = PathBuf::from(&entry.path);
+        if !hashes.contains(&entry.hash) {
+            if path.exists() {
+                fs::remove_file(&path)?;
+                report.freed_bytes += entry.size as u64;
+            }
+            report.removed_orphans.push(entry.path.to_owned());
+        } else if !path.exists() {
+            if !entry.derived {
+                missing.insert(entry.hash.to_owned());
+            }
+            report.dropped.push(entry.path.to_owned());
+        } else {
+            continue;
+        }
+        query!("delete from cache_index where path = ?", entry.path)
+            .execute(&db)
+            .await?;
+    }
+
+    // Files the index doesn't know about
+    let known = indexed
+        .iter()
+        .map(|entry| PathBuf::from(&entry.path))
+        .collect::<HashSet<_>>();
+    for file in fs::read_dir(&cache_dir)?.filter_map(|f| f.ok()) {
+        let path = file.path();
+        // Leftovers of interrupted downloads. Recent ones may still be in progress
+        if path.extension().is_some_and(|e| e == PARTIAL_EXTENSION) {
+            let metadata = file.metadata()?;
+            if metadata
+                .modified()?
+                .elapsed()
+                .is_ok_and(|age| age > STALE_PARTIAL)
+            {
+                fs::remove_file(&path)?;
+                report.freed_bytes += metadata.len();
+                report
+                    .removed_orphans
+                    .push(file.file_name().to_string_lossy().to_string());
+            }
+            continue;
+        }
+        if known.contains(&path)
+            || !file.file_type().is_ok_and(|t| t.is_file())
+            || !mime_guess::from_path(&path)
+                .iter()
+                .any(|mime| mime.type_() == IMAGE)
+        {
+            continue;
+        }
+        let name = file.file_name().to_string_lossy().to_string();
+        let hash = file_hash(&path).unwrap_or_default();
+        let derived = name.matches('.').count() > 1;
+        if hashes.contains(&hash) {
+            record_file(app, &hash, &path, derived).await?;
+            report.adopted.push(name);
+            continue;
+        }
+        // Downloads from before the cache was content addressed are named after their URL
+        if !derived && let Some(wallpaper) = queued.iter().find(|w| hash_url(&w.data_url) == hash) {
+            let hash = sha256::try_digest(path.as_path())?;
+            let extension = path.extension().unwrap_or_default().to_string_lossy();
+            let renamed = cache_dir.join(format!("{hash}.{extension}"));
+            fs::rename(&path, &renamed)?;
+            record_content(app, &wallpaper.id, &hash).await?;
+            record_file(app, &hash, &renamed, false).await?;
+            report.adopted.push(name);
+            continue;
+        }
+        report.freed_bytes += file.metadata().map(|m| m.len()).unwrap_or(0);
+        fs::remove_file(&path)?;
+        report.removed_orphans.push(name);
+    }
+
+    // Downloading takes the lock too
+    drop(scanning);
+
+    // Files which matter are fetched again, the rest will be downloaded when they're needed
+    for wallpaper in &queued {
+        let important = favorites.contains(&wallpaper.id) || current == Some(wallpaper.id.as_str());
+        if !important
+            || !contents
+                .get(&wallpaper.id)
+                .is_some_and(|hash| missing.contains(hash))
+        {
+            continue;
+        }
+        if cached_file(app, &wallpaper.id).await?.is_none() {
+            match download_wallpaper(app, wallpaper).await {
+                Ok(_) => report.redownloaded.push(wallpaper.id.to_owned()),
+                Err(err) => app.log(
+                    &format!("Couldn't download {} again: {err}", wallpaper.id),
+                    LogLevel::Error,
+                ),
+            }
+        }
+    }
+
+    app.log(&report, LogLevel::Info);
+    Ok(report)
+}
+
+#[command]
+pub async fn verify_cache(app: AppHandle) -> Result<CacheReport> {
+    let current = current_wallpaper(&app).await?.map(|w| w.id);
+    verify(&app, current.as_deref()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::tests::database;
+    use chrono::NaiveDate;
+
+    /// Indexes a file of the given content, last used at noon on the given day of October 2026
+    async fn index(db: &DB, path: &str, hash: &str, day: u32, pinned: bool) {
+        let accessed = NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        sqlx::query(
+            "insert into cache_index (path, hash, size, accessed, pinned) values ($1, $2, 100, $3, $4)",
+        )
+        .bind(path)
+        .bind(hash)
+        .bind(accessed)
+        .bind(pinned)
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    async fn content(db: &DB, id: &str, hash: &str) {
+        sqlx::query("insert into contents (id, hash) values ($1, $2)")
+            .bind(id)
+            .bind(hash)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    async fn candidates(db: &DB, current: Option<&str>) -> Vec<String> {
+        eviction_candidates(db, current)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_first() {
+        let db = database().await;
+        index(&db, "b.jpg", "b", 2, false).await;
+        index(&db, "c.jpg", "c", 3, false).await;
+        index(&db, "a.jpg", "a", 1, false).await;
+        assert_eq!(candidates(&db, None).await, ["a.jpg", "b.jpg", "c.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn pinned_files_stay() {
+        let db = database().await;
+        index(&db, "a.jpg", "a", 1, true).await;
+        index(&db, "b.jpg", "b", 2, false).await;
+        assert_eq!(candidates(&db, None).await, ["b.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn favorites_and_the_current_wallpaper_stay() {
+        let db = database().await;
+        index(&db, "a.jpg", "a", 1, false).await;
+        // Processed copies go with their original
+        index(&db, "a.fill-1920x1080-000000.jpg", "a", 2, false).await;
+        index(&db, "b.jpg", "b", 3, false).await;
+        index(&db, "c.jpg", "c", 4, false).await;
+        content(&db, "favorite", "a").await;
+        content(&db, "current", "b").await;
+        content(&db, "other", "c").await;
+        sqlx::query("insert into favorites (id, date) values ('favorite', '2026-10-01 12:00:00')")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(candidates(&db, Some("current")).await, ["c.jpg"]);
+        assert_eq!(candidates(&db, None).await, ["b.jpg", "c.jpg"]);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{app_handle_ext::AppHandleExt, log::LogLevel};
+use anyhow::Result;
+use macros::command;
+use regex_macro::regex;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use ts_rs::TS;
+
+/// A monitor which can be given its own wallpaper
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct Output {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Manual overrides for a single output, keyed by the output's name in the config
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct DisplayConfig {
+    /// Overrides the detected width. Required (with height) for outputs which aren't detected
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Sources this output picks wallpapers from - all sources if empty
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// Leave this output alone when changing wallpapers
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+/// Query the display server for connected outputs
+pub async fn detect_outputs() -> Result<Vec<Output>> {
+    #[cfg(target_os = "linux")]
+    {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            let output = tokio::process::Command::new("wlr-randr").output().await?;
+            if output.status.success() {
+                return Ok(parse_wlr_randr(&String::from_utf8_lossy(&output.stdout)));
+            }
+        }
+        let output = tokio::process::Command::new("xrandr").arg("--query").output().await?;
+        Ok(parse_xrandr(&String::from_utf8_lossy(&output.stdout)))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Ok(Vec::new())
+    }
+}
+
+/// Parses the current mode of each connected output, e.g.
+/// `HDMI-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 527mm x 296mm`
+pub fn parse_xrandr(output: &str) -> Vec<Output> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace) && line.contains(" connected"))
+        .filter_map(|line| {
+            let name = line.split_whitespace().next()?;
+            let mode = regex!(r"(\d+)x(\d+)\+\d+\+\d+").captures(line)?;
+            Some(Output {
+                name: name.to_string(),
+                width: mode[1].parse().ok()?,
+                height: mode[2].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Parses wlr-randr's output blocks, using the mode marked as current
+pub fn parse_wlr_randr(output: &str) -> Vec<Output> {
+    let mut outputs = Vec::new();
+    let mut current: Option<(Output, bool)> = None;
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            outputs.extend(current.take().and_then(|(o, enabled)| enabled.then_some(o)));
+            if let Some(name) = line.split_whitespace().next() {
+                current = Some((
+                    Output {
+                        name: name.to_string(),
+                        width: 0,
+                        height: 0,
+                    },
+                    true,
+                ));
+            }
+            continue;
+        }
+        let Some((output, enabled)) = &mut current else {
+            continue;
+        };
+        let line = line.trim();
+        if line == "Enabled: no" {
+            *enabled = false;
+        } else if line.contains("current")
+            && let Some(mode) = regex!(r"^(\d+)x(\d+) px").captures(line)
+        {
+            output.width = mode[1].parse().unwrap_or(0);
+            output.height = mode[2].parse().unwrap_or(0);
+        } else if let Some(transform) = line.strip_prefix("Transform: ")
+            && (transform.ends_with("90") || transform.ends_with("270"))
+        {
+            std::mem::swap(&mut output.width, &mut output.height);
+        }
+    }
+    outputs.extend(current.and_then(|(o, enabled)| enabled.then_some(o)));
+    outputs.retain(|o| o.width > 0 && o.height > 0);
+    outputs
+}
+
+/// Detected outputs with the config's overrides applied, plus any manually defined outputs
+pub async fn outputs(app: &AppHandle) -> Vec<(Output, DisplayConfig)> {
+    let displays = app.get_config().await.displays;
+    let mut detected = detect_outputs().await.unwrap_or_else(|err| {
+        app.log(&format!("Couldn't detect outputs: {err}"), LogLevel::Debug);
+        Vec::new()
+    });
+    for (name, display) in &displays {
+        if !detected.iter().any(|o| &o.name == name)
+            && let (Some(width), Some(height)) = (display.width, display.height)
+        {
+            detected.push(Output {
+                name: name.to_owned(),
+                width,
+                height,
+            });
+        }
+    }
+    detected
+        .into_iter()
+        .map(|mut output| {
+            let display = displays.get(&output.name).cloned().unwrap_or_default();
+            output.width = display.width.unwrap_or(output.width);
+            output.height = display.height.unwrap_or(output.height);
+            (output, display)
+        })
+        .filter(|(_, display)| !display.disabled)
+        .collect()
+}
+
+#[command]
+pub async fn get_outputs(app: AppHandle) -> Result<Vec<Output>> {
+    Ok(outputs(&app).await.into_iter().map(|(o, _)| o).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &str, width: u32, height: u32) -> Output {
+        Output {
+            name: name.to_string(),
+            width,
+            height,
+        }
+    }
+
+    const XRANDR: &str = "\
+Screen 0: minimum 320 x 200, current 3640 x 1920, maximum 16384 x 16384
+eDP-1 connected primary 2560x1440+0+0 (normal left inverted right x axis y axis) 344mm x 194mm
+   2560x1440     60.00*+  48.00
+   1920x1080     60.01    59.97    59.96    59.93
+HDMI-1 disconnected (normal left inverted right x axis y axis)
+DP-1 connected 1080x1920+2560+0 left (normal left inverted right x axis y axis) 527mm x 296mm
+   1920x1080     60.00*+  74.97    50.00    59.94
+   1280x720      60.00    50.00    59.94
+DP-2 connected (normal left inverted right x axis y axis)
+   1920x1080     60.00 +
+";
+
+    const WLR_RANDR: &str = "\
+DP-1 \"Dell Inc. DELL U2720Q 5KC0J23 (DP-1)\"
+  Make: Dell Inc.
+  Model: DELL U2720Q
+  Serial: 5KC0J23
+  Physical size: 600x340 mm
+  Enabled: yes
+  Modes:
+    3840x2160 px, 59.997002 Hz (preferred, current)
+    2560x1440 px, 59.951000 Hz
+    1920x1080 px, 60.000000 Hz
+  Position: 0,0
+  Transform: normal
+  Scale: 1.500000
+  Adaptive Sync: disabled
+HDMI-A-1 \"Ancor Communications Inc ASUS VS228 K3LMQS0XXXXX (HDMI-A-1)\"
+  Make: Ancor Communications Inc
+  Model: ASUS VS228
+  Serial: K3LMQS0XXXXX
+  Physical size: 480x270 mm
+  Enabled: yes
+  Modes:
+    1920x1080 px, 60.000000 Hz (preferred, current)
+    1280x720 px, 60.000000 Hz
+  Position: 2560,0
+  Transform: 90
+  Scale: 1.000000
+  Adaptive Sync: disabled
+DP-2 \"Unknown Unknown Unknown (DP-2)\"
+  Enabled: yes
+  Modes:
+    2560x1440 px, 143.912003 Hz (current)
+  Position: 3640,0
+  Transform: flipped-270
+  Scale: 1.000000
+eDP-1 \"Sharp Corporation 0x14D0 Unknown (eDP-1)\"
+  Make: Sharp Corporation
+  Physical size: 290x180 mm
+  Enabled: no
+  Modes:
+    2560x1600 px, 60.002000 Hz (preferred)
+";
+
+    #[test]
+    fn xrandr_connected_outputs() {
+        // Disconnected outputs, and connected ones which are switched off, are left out
+        assert_eq!(
+            parse_xrandr(XRANDR),
+            [output("eDP-1", 2560, 1440), output("DP-1", 1080, 1920)]
+        );
+    }
+
+    #[test]
+    fn xrandr_nothing_connected() {
+        assert!(
+            parse_xrandr("HDMI-1 disconnected (normal left inverted right x axis y axis)\n")
+                .is_empty()
+        );
+        assert!(parse_xrandr("").is_empty());
+    }
+
+    #[test]
+    fn wlr_randr_enabled_outputs() {
+        // Rotated outputs swap their width and height, disabled ones are left out
+        assert_eq!(
+            parse_wlr_randr(WLR_RANDR),
+            [
+                output("DP-1", 3840, 2160),
+                output("HDMI-A-1", 1080, 1920),
+                output("DP-2", 1440, 2560),
+            ]
+        );
+    }
+
+    #[test]
+    fn wlr_randr_without_current_mode() {
+        let enabled_without_mode = "\
+HDMI-A-2 \"Unknown\"
+  Enabled: yes
+  Modes:
+    1920x1080 px, 60.000000 Hz (preferred)
+";
+        assert!(parse_wlr_randr(enabled_without_mode).is_empty());
+    }
+}
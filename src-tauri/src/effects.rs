@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, Rgba};
+use imageproc::drawing::{draw_text_mut, text_size};
+use rusttype::{Font, Scale};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+use ts_rs::TS;
+
+/// Fonts tried for overlays which don't specify one
+const FALLBACK_FONTS: [&str; 5] = [
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+    "C:\\Windows\\Fonts\\segoeui.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+/// A change made to wallpapers after they've been scaled. Effects are applied in order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub enum Effect {
+    /// Gaussian blur with the given standard deviation, in pixels
+    Blur {
+        sigma: f32,
+    },
+    /// Added to every channel, from -255 (black) to 255 (white)
+    Brightness {
+        amount: i32,
+    },
+    /// Percentage to change the contrast by. Negative values reduce it
+    Contrast {
+        amount: f32,
+    },
+    /// 0 removes all colour, 1 keeps the image as is and larger values boost colours
+    Saturation {
+        factor: f32,
+    },
+    Grayscale,
+    /// Mixes `color` (hex) into the image. A strength of 1 replaces it entirely
+    Tint {
+        color: String,
+        strength: f32,
+    },
+    /// Writes the wallpaper's name in a corner
+    NameOverlay {
+        /// Text height, in pixels
+        size: f32,
+        /// Hex colour
+        color: String,
+        #[serde(default)]
+        corner: Corner,
+        /// A TrueType font. Falls back to a common system font
+        #[serde(default)]
+        font: Option<PathBuf>,
+    },
+}
+
+/// Parses colours like `#663399`
+pub fn parse_color(color: &str) -> Result<Rgba<u8>> {
+    let bytes = hex::decode(color.trim_start_matches('#'))?;
+    match bytes[..] {
+        [r, g, b] => Ok(Rgba([r, g, b, 255])),
+        [r, g, b, a] => Ok(Rgba([r, g, b, a])),
+        _ => Err(anyhow!("Invalid colour \"{color}\"")),
+    }
+}
+
+fn load_font(font: &Option<PathBuf>) -> Result<Font<'static>> {
+    let data = match font {
+        Some(font) => fs::read(font)?,
+        None => FALLBACK_FONTS
+            .iter()
+            .find_map(|font| fs::read(font).ok())
+            .ok_or(anyhow!("No font found for the name overlay"))?,
+    };
+    Font::try_from_vec(data).ok_or(anyhow!("Invalid font"))
+}
+
+/// Applies `effects` in order. `name` is used by name overlays, which are skipped without one
+pub fn apply_effects(
+    image: DynamicImage,
+    effects: &[Effect],
+    name: Option<&str>,
+) -> Result<DynamicImage> {
+    let mut image = image;
+    for effect in effects {
+        image = match effect {
+            Effect::Blur { sigma } => image.blur(*sigma),
+            Effect::Brightness { amount } => image.brighten(*amount),
+            Effect::Contrast { amount } => image.adjust_contrast(*amount),
+            Effect::Saturation { factor } => {
+                let mut rgba = image.to_rgba8();
+                for pixel in rgba.pixels_mut() {
+                    let [r, g, b, _] = pixel.0.map(|c| c as f32);
+                    let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                    for channel in &mut pixel.0[..3] {
+                        *channel =
+                            (luma + (*channel as f32 - luma) * factor).clamp(0.0, 255.0) as u8;
+                    }
+                }
+                DynamicImage::ImageRgba8(rgba)
+            }
+            Effect::Grayscale => DynamicImage::ImageRgba8(image.grayscale().to_rgba8()),
+            Effect::Tint { color, strength } => {
+                let tint = parse_color(color)?;
+                let strength = strength.clamp(0.0, 1.0);
+                let mut rgba = image.to_rgba8();
+                for pixel in rgba.pixels_mut() {
+                    for (channel, tint) in pixel.0[..3].iter_mut().zip(tint.0) {
+                        *channel =
+                            (*channel as f32 * (1.0 - strength) + tint as f32 * strength) as u8;
+                    }
+                }
+                DynamicImage::ImageRgba8(rgba)
+            }
+            Effect::NameOverlay {
+                size,
+                color,
+                corner,
+                font,
+            } => {
+                let Some(name) = name else {
+                    continue;
+                };
+                let font = load_font(font)?;
+                let scale = Scale::uniform(*size);
+                let (text_width, text_height) = text_size(scale, &font, name);
+                let margin = *size as i32;
+                let (width, height) = (image.width() as i32, image.height() as i32);
+                let x = match corner {
+                    Corner::TopLeft | Corner::BottomLeft => margin,
+                    Corner::TopRight | Corner::BottomRight => width - text_width - margin,
+                };
+                let y = match corner {
+                    Corner::TopLeft | Corner::TopRight => margin,
+                    Corner::BottomLeft | Corner::BottomRight => height - text_height - margin,
+                };
+                let mut rgba = image.to_rgba8();
+                draw_text_mut(&mut rgba, parse_color(color)?, x, y, scale, &font, name);
+                DynamicImage::ImageRgba8(rgba)
+            }
+        };
+    }
+    Ok(image)
+}
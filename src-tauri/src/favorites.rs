@@ -0,0 +1,101 @@
+use crate::{app_handle_ext::AppHandleExt, queue::current_wallpaper};
+use anyhow::{anyhow, Result};
+use macros::command;
+use reddw_source_plugin::Wallpaper;
+use sqlx::{query, query_as};
+use tauri::{AppHandle, Manager};
+
+pub async fn is_favorite(app: &AppHandle, id: &str) -> Result<bool> {
+    let favorite = query!("select id from favorites where id = ?", id)
+        .fetch_optional(&app.db().await)
+        .await?;
+    Ok(favorite.is_some())
+}
+
+/// A random favorite, other than the current wallpaper when possible
+pub async fn random_favorite(app: &AppHandle) -> Result<Wallpaper> {
+    query_as!(
+        Wallpaper,
+        "---sql
+        select queue.* from queue
+        join favorites on favorites.id = queue.id
+        order by queue.id = (
+            select id from queue
+            where was_set = 1 and id not in (select id from retired)
+            order by date desc limit 1
+        ), random()
+        limit 1"
+    )
+    .fetch_optional(&app.db().await)
+    .await?
+    .ok_or(anyhow!("No favorites"))
+}
+
+/// Keeps the tray's favorite toggle in line with the current wallpaper
+pub async fn update_tray(app: &AppHandle, id: &str) -> Result<()> {
+    let title = if is_favorite(app, id).await? {
+        "Unfavorite"
+    } else {
+        "Favorite"
+    };
+    app.tray_handle()
+        .get_item("toggle_favorite")
+        .set_title(title)?;
+    Ok(())
+}
+
+async fn set_favorite(app: &AppHandle, id: &str, favorite: bool) -> Result<()> {
+    let db = app.db().await;
+    if favorite {
+        let now = chrono::Utc::now().naive_utc();
+        query!(
+            "insert or ignore into favorites (id, date) values ($1, $2)",
+            id,
+            now
+        )
+        .execute(&db)
+        .await?;
+    } else {
+        query!("delete from favorites where id = ?", id)
+            .execute(&db)
+            .await?;
+    }
+    if current_wallpaper(app).await?.is_some_and(|w| w.id == id) {
+        update_tray(app, id).await?;
+    }
+    app.emit_all("favorites_changed", (id, favorite))?;
+    Ok(())
+}
+
+/// Favorites or unfavorites the current wallpaper
+pub async fn toggle_current(app: &AppHandle) -> Result<()> {
+    let Some(wallpaper) = current_wallpaper(app).await? else {
+        return Ok(());
+    };
+    let favorite = !is_favorite(app, &wallpaper.id).await?;
+    set_favorite(app, &wallpaper.id, favorite).await
+}
+
+#[command]
+pub async fn favorite(app: AppHandle, id: String) -> Result<()> {
+    set_favorite(&app, &id, true).await
+}
+
+#[command]
+pub async fn unfavorite(app: AppHandle, id: String) -> Result<()> {
+    set_favorite(&app, &id, false).await
+}
+
+#[command]
+pub async fn get_favorites(app: AppHandle) -> Result<Vec<Wallpaper>> {
+    let favorites = query_as!(
+        Wallpaper,
+        "---sql
+        select queue.* from queue
+        join favorites on favorites.id = queue.id
+        order by favorites.date desc"
+    )
+    .fetch_all(&app.db().await)
+    .await?;
+    Ok(favorites)
+}
@@ -0,0 +1,220 @@
+use crate::{app_handle_ext::AppHandleExt, cache, favorites::is_favorite, log::LogLevel};
+use anyhow::Result;
+use macros::command;
+use reddw_source_plugin::Wallpaper;
+use serde::{Deserialize, Serialize};
+use sqlx::query;
+use std::path::Path;
+use tauri::{AppHandle, Manager};
+use ts_rs::TS;
+
+/// Accepted width to height ratios, inclusive. A 16:9 image has a ratio of about 1.78
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct AspectRatioRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Requirements a wallpaper's dimensions must meet before it gets set
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct ImageFilter {
+    #[serde(default)]
+    pub min_width: Option<u32>,
+    #[serde(default)]
+    pub min_height: Option<u32>,
+    /// Any aspect ratio is accepted if empty
+    #[serde(default)]
+    pub aspect_ratios: Vec<AspectRatioRange>,
+}
+
+impl ImageFilter {
+    pub fn is_empty(&self) -> bool {
+        self.min_width.is_none() && self.min_height.is_none() && self.aspect_ratios.is_empty()
+    }
+
+    /// Why an image of the given size doesn't pass, if it doesn't
+    pub fn check(&self, width: u32, height: u32) -> Option<String> {
+        if let Some(min_width) = self.min_width
+            && width < min_width
+        {
+            return Some(format!(
+                "{width}px wide, below the minimum of {min_width}px"
+            ));
+        }
+        if let Some(min_height) = self.min_height
+            && height < min_height
+        {
+            return Some(format!(
+                "{height}px tall, below the minimum of {min_height}px"
+            ));
+        }
+        let ratio = width as f64 / height.max(1) as f64;
+        if !self.aspect_ratios.is_empty()
+            && !self
+                .aspect_ratios
+                .iter()
+                .any(|range| (range.min..=range.max).contains(&ratio))
+        {
+            return Some(format!(
+                "aspect ratio {ratio:.2} is outside the allowed ranges"
+            ));
+        }
+        None
+    }
+}
+
+/// The filter applying to a source - its own if it has one, the global one otherwise
+pub async fn filter_for(app: &AppHandle, source: &str) -> ImageFilter {
+    let config = app.get_config().await;
+    config
+        .source_filters
+        .get(source)
+        .cloned()
+        .unwrap_or(config.filter)
+}
+
+/// Reads an image's dimensions from its header
+pub fn probe_dimensions(path: &Path) -> Result<(u32, u32)> {
+    let size = imagesize::size(path)?;
+    Ok((size.width as u32, size.height as u32))
+}
+
+/// Stores probed dimensions, so they don't need probing again
+pub async fn record_dimensions(app: &AppHandle, id: &str, width: u32, height: u32) -> Result<()> {
+    query!(
+        "update queue set width = $1, height = $2 where id = $3",
+        width,
+        height,
+        id
+    )
+    .execute(&app.db().await)
+    .await?;
+    Ok(())
+}
+
+/// Takes a wallpaper out of the queue and the cache for not passing its filter.
+/// Rejections are recorded so the source isn't asked for the wallpaper again.
+/// Favorites were chosen by hand, so they're kept regardless
+pub async fn reject_wallpaper(app: &AppHandle, wallpaper: &Wallpaper, reason: &str) -> Result<()> {
+    if is_favorite(app, &wallpaper.id).await? {
+        return Ok(());
+    }
+    let db = app.db().await;
+    let now = chrono::Utc::now().naive_utc();
+    query!(
+        "insert or replace into rejections (id, source, data_url, reason, date) values ($1, $2, $3, $4, $5)",
+        wallpaper.id,
+        wallpaper.source,
+        wallpaper.data_url,
+        reason,
+        now
+    )
+    .execute(&db)
+    .await?;
+    query!("delete from queue where id = ?", wallpaper.id)
+        .execute(&db)
+        .await?;
+
+    cache::remove_files(app, &wallpaper.id).await?;
+    app.log(
+        &format!("Skipped {}: {reason}", wallpaper.id),
+        LogLevel::Info,
+    );
+    Ok(())
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct Rejection {
+    pub id: String,
+    pub source: String,
+    pub data_url: String,
+    pub reason: String,
+    #[ts(type = "string")]
+    pub date: chrono::NaiveDateTime,
+}
+
+#[command]
+pub async fn get_rejections(app: AppHandle) -> Result<Vec<Rejection>> {
+    let rejections = sqlx::query_as!(
+        Rejection,
+        "select id, source, data_url, reason, date from rejections order by date desc"
+    )
+    .fetch_all(&app.db().await)
+    .await?;
+    Ok(rejections)
+}
+
+/// Forgets rejections, so sources may offer those wallpapers again. Useful after relaxing a filter
+#[command]
+pub async fn clear_rejections(app: AppHandle) -> Result<()> {
+    query!("delete from rejections")
+        .execute(&app.db().await)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratios(ranges: &[(f64, f64)]) -> ImageFilter {
+        ImageFilter {
+            aspect_ratios: ranges
+                .iter()
+                .map(|&(min, max)| AspectRatioRange { min, max })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn empty_filter_passes_everything() {
+        let filter = ImageFilter::default();
+        assert!(filter.is_empty());
+        assert_eq!(filter.check(1, 1), None);
+        assert_eq!(filter.check(0, 0), None);
+    }
+
+    #[test]
+    fn minimum_size() {
+        let filter = ImageFilter {
+            min_width: Some(1920),
+            min_height: Some(1080),
+            ..Default::default()
+        };
+        assert!(!filter.is_empty());
+        assert_eq!(filter.check(1920, 1080), None);
+        assert_eq!(
+            filter.check(1280, 1080).as_deref(),
+            Some("1280px wide, below the minimum of 1920px")
+        );
+        assert_eq!(
+            filter.check(1920, 720).as_deref(),
+            Some("720px tall, below the minimum of 1080px")
+        );
+    }
+
+    #[test]
+    fn aspect_ratio_ranges() {
+        let filter = ratios(&[(1.7, 1.8), (2.3, 2.4)]);
+        assert_eq!(filter.check(1920, 1080), None);
+        assert_eq!(filter.check(3440, 1440), None);
+        // Ranges are inclusive
+        assert_eq!(filter.check(170, 100), None);
+        assert_eq!(filter.check(180, 100), None);
+        assert_eq!(
+            filter.check(1080, 1920).as_deref(),
+            Some("aspect ratio 0.56 is outside the allowed ranges")
+        );
+    }
+
+    #[test]
+    fn zero_height_doesnt_divide_by_zero() {
+        // The height is taken as 1, making the ratio the width
+        assert_eq!(ratios(&[(1.0, 2.0)]).check(2, 0), None);
+        assert!(ratios(&[(1.0, 2.0)]).check(1920, 0).is_some());
+    }
+}
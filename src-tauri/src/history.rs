@@ -0,0 +1,37 @@
+use crate::{queue::history_entry, wallpaper_changer::show_wallpaper};
+use anyhow::{anyhow, Result};
+use macros::command;
+use tauri::{async_runtime::Mutex, AppHandle, Manager};
+
+/// How many steps back into the history the displayed wallpaper is. 0 is the newest entry
+pub type HistoryCursor = Mutex<i64>;
+
+pub fn setup_history(app: AppHandle) -> bool {
+    app.manage(HistoryCursor::new(0))
+}
+
+/// Displays the history entry `steps` away from the current one, without touching the history itself
+async fn step(app: &AppHandle, steps: i64) -> Result<()> {
+    let cursor = app.state::<HistoryCursor>();
+    let target = *cursor.lock().await + steps;
+    if target < 0 {
+        return Err(anyhow!("Already at the newest wallpaper"));
+    }
+    let wallpaper = history_entry(app, target)
+        .await?
+        .ok_or(anyhow!("No older wallpapers in the history"))?;
+    // Showing the wallpaper may look up the current one, which locks the cursor too
+    show_wallpaper(app, &wallpaper).await?;
+    *cursor.lock().await = target;
+    Ok(())
+}
+
+#[command]
+pub async fn previous_wallpaper(app: AppHandle) -> Result<()> {
+    step(&app, 1).await
+}
+
+#[command]
+pub async fn next_wallpaper(app: AppHandle) -> Result<()> {
+    step(&app, -1).await
+}
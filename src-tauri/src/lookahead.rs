@@ -0,0 +1,177 @@
+use crate::{
+    app_handle_ext::AppHandleExt,
+    displays::Output,
+    filters,
+    log::LogLevel,
+    processing::process_wallpaper,
+    wallpaper_changer::{choose_wallpaper, wallpaper_targets},
+};
+use anyhow::Result;
+use reddw_source_plugin::Wallpaper;
+use sqlx::query;
+use std::path::PathBuf;
+use tauri::{
+    async_runtime::{spawn, Mutex},
+    AppHandle, Manager,
+};
+
+/// A wallpaper picked and downloaded ahead of time, so the next change doesn't wait on the network
+pub struct Prepared {
+    /// Name of the output it's for, `None` when every output shows the same wallpaper
+    output: Option<String>,
+    wallpaper: Wallpaper,
+    path: PathBuf,
+}
+
+/// The wallpapers the next change will use, one per target
+pub type Lookahead = Mutex<Vec<Prepared>>;
+
+pub fn setup_lookahead(app: AppHandle) -> bool {
+    app.manage(Lookahead::default())
+}
+
+/// Whether a prepared wallpaper may still be set on a target picking from `sources`.
+/// Wallpapers stop being valid when they're banned, their source is removed, or the rules
+/// for picking them change
+async fn is_valid(app: &AppHandle, prepared: &Prepared, sources: &[String]) -> Result<bool> {
+    let wallpaper = &prepared.wallpaper;
+    if !prepared.path.exists() {
+        return Ok(false);
+    }
+    let db = app.db().await;
+    if app.get_config().await.favorites_only {
+        let favorite = query!("select id from favorites where id = ?", wallpaper.id)
+            .fetch_optional(&db)
+            .await?;
+        return Ok(favorite.is_some());
+    }
+    if !sources.contains(&wallpaper.source) {
+        return Ok(false);
+    }
+    if let Some((width, height)) = wallpaper.width.zip(wallpaper.height)
+        && filters::filter_for(app, &wallpaper.source)
+            .await
+            .check(width as u32, height as u32)
+            .is_some()
+    {
+        return Ok(false);
+    }
+    let queued = query!(
+        "---sql
+        select id from queue
+        where id = $1 and was_set = 0
+            and id not in (select id from bans)
+            and id not in (select id from rejections)",
+        wallpaper.id
+    )
+    .fetch_optional(&db)
+    .await?;
+    Ok(queued.is_some())
+}
+
+/// Takes the wallpaper prepared for `output`, if there's one which is still valid.
+/// Never waits on the lookahead, so a change can always fall back to picking one itself
+pub async fn take_prepared(
+    app: &AppHandle,
+    output: Option<&Output>,
+    sources: &[String],
+) -> Result<Option<(Wallpaper, PathBuf)>> {
+    let name = output.map(|output| &output.name);
+    let prepared = {
+        let lookahead = app.state::<Lookahead>();
+        let Ok(mut lookahead) = lookahead.try_lock() else {
+            return Ok(None);
+        };
+        let Some(index) = lookahead
+            .iter()
+            .position(|prepared| prepared.output.as_ref() == name)
+        else {
+            return Ok(None);
+        };
+        lookahead.remove(index)
+    };
+    if !is_valid(app, &prepared, sources).await? {
+        app.log(
+            &format!("Discarded prepared wallpaper {}", prepared.wallpaper.id),
+            LogLevel::Debug,
+        );
+        return Ok(None);
+    }
+    Ok(Some((prepared.wallpaper, prepared.path)))
+}
+
+/// Swaps out prepared wallpapers which are no longer valid, and prepares one for each target
+/// missing one. The lookahead is only locked to check and store wallpapers, never while
+/// one is being downloaded or processed
+async fn prepare_next(app: &AppHandle) -> Result<()> {
+    let lookahead = app.state::<Lookahead>();
+    let targets = wallpaper_targets(app).await;
+
+    {
+        let mut lookahead = lookahead.lock().await;
+        let mut kept = Vec::new();
+        for prepared in std::mem::take(&mut *lookahead) {
+            let sources = targets
+                .iter()
+                .find(|(output, _)| output.as_ref().map(|o| &o.name) == prepared.output.as_ref())
+                .map(|(_, sources)| sources);
+            if let Some(sources) = sources
+                && is_valid(app, &prepared, sources).await?
+            {
+                kept.push(prepared);
+            } else {
+                app.log(
+                    &format!("Swapping out prepared wallpaper {}", prepared.wallpaper.id),
+                    LogLevel::Debug,
+                );
+            }
+        }
+        *lookahead = kept;
+    }
+
+    for (output, sources) in targets {
+        let name = output.as_ref().map(|output| output.name.clone());
+        // Each output should get a different wallpaper
+        let reserved = {
+            let lookahead = lookahead.lock().await;
+            if lookahead.iter().any(|prepared| prepared.output == name) {
+                continue;
+            }
+            lookahead
+                .iter()
+                .map(|prepared| prepared.wallpaper.id.clone())
+                .collect::<Vec<_>>()
+        };
+        let (wallpaper, path) = choose_wallpaper(app, &sources, &reserved).await?;
+        // Processing ahead too leaves nothing slow for the change itself
+        process_wallpaper(app, &wallpaper, &path, output.as_ref()).await?;
+        let mut lookahead = lookahead.lock().await;
+        // Another refresh may have beaten this one to it
+        if lookahead.iter().any(|prepared| prepared.output == name) {
+            continue;
+        }
+        app.log(
+            &format!("Prepared the next wallpaper: {}", wallpaper.id),
+            LogLevel::Debug,
+        );
+        lookahead.push(Prepared {
+            output: name,
+            wallpaper,
+            path,
+        });
+    }
+    Ok(())
+}
+
+/// Prepares the next wallpapers in the background
+pub fn refresh_lookahead(app: AppHandle) {
+    spawn(async move {
+        // The next change picks a wallpaper itself if this fails, so it's not worth an error
+        prepare_next(&app).await.unwrap_or_else(|err| {
+            app.log(
+                &format!("Couldn't prepare the next wallpaper: {err}"),
+                LogLevel::Debug,
+            )
+        });
+    });
+}
@@ -5,14 +5,37 @@
 mod app_config;
 mod app_handle_ext;
 mod automation_socket;
+mod bans;
+mod cache;
+mod displays;
+mod effects;
+mod favorites;
+mod filters;
+mod history;
 mod log;
+mod lookahead;
+mod pause;
+mod prefetch;
+mod processing;
 mod queue;
+mod retry;
+mod schedule;
 mod source_host;
 mod tray;
 mod wallpaper_changer;
 mod watcher;
 use crate::{
     app_config::{get_config, select_folder, select_file, update_command::update_config},
+    app_handle_ext::AppHandleExt,
+    bans::{ban, ban_current, unban},
+    cache::{pin, setup_cache, unpin, verify_cache},
+    displays::get_outputs,
+    favorites::{favorite, get_favorites, unfavorite},
+    filters::{clear_rejections, get_rejections},
+    history::{next_wallpaper, previous_wallpaper, setup_history},
+    log::LogLevel,
+    pause::{get_pause, pause, pause_until, resume, setup_pause, snooze_current},
+    prefetch::{cancel_prefetch, setup_prefetch},
     queue::{cache_queue, get_queue, refresh_source_queue},
     source_host::{load_plugin_ui, query_available_source_plugins},
     wallpaper_changer::{get_wallpaper, set_wallpaper, update_wallpaper},
@@ -20,6 +43,7 @@ use crate::{
 use anyhow::{anyhow, Result};
 use automation_socket::Args;
 use clap::Parser;
+use lookahead::{refresh_lookahead, setup_lookahead};
 use queue::manage_queue;
 use source_host::host_sources;
 use tauri::{async_runtime::block_on, generate_handler, AppHandle, Manager, Window};
@@ -77,11 +101,16 @@ fn main() {
                     .map(|w| w.show())??;
             };
 
-            let tx_interval = setup_changer(app.handle());
+            setup_pause(app.handle());
+            setup_history(app.handle());
+            setup_prefetch(app.handle());
+            setup_cache(app.handle());
+            setup_lookahead(app.handle());
+            let tx_rotation = setup_changer(app.handle());
 
             match {
                 // Setup config + config watcher
-                app_config::build(app.handle(), tx_interval)?;
+                app_config::build(app.handle(), tx_rotation)?;
 
                 // Setup history + queue
                 block_on(manage_queue(&app.handle()))?;
@@ -94,6 +123,25 @@ fn main() {
                 }
             }
             block_on(host_sources(app.handle()))?;
+            refresh_lookahead(app.handle());
+            if args.verify_cache {
+                let app = app.handle();
+                tauri::async_runtime::spawn(async move {
+                    let current = queue::current_wallpaper(&app).await;
+                    let verified = match current {
+                        Ok(current) => cache::verify(&app, current.map(|w| w.id).as_deref()).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = verified {
+                        // Asked for on the command line, so it's reported there whatever the logging
+                        eprintln!("Cache verification failed: {err:#}");
+                        app.log(
+                            &format!("Cache verification failed: {err}"),
+                            LogLevel::Error,
+                        );
+                    }
+                });
+            }
             Ok(())
         })
         .invoke_handler(generate_handler![
@@ -103,12 +151,32 @@ fn main() {
             query_available_source_plugins,
             load_plugin_ui,
             cache_queue,
+            cancel_prefetch,
             get_queue,
             select_folder,
             select_file,
             set_wallpaper,
             refresh_source_queue,
             get_wallpaper,
+            previous_wallpaper,
+            next_wallpaper,
+            get_outputs,
+            ban,
+            ban_current,
+            unban,
+            favorite,
+            unfavorite,
+            get_favorites,
+            pin,
+            unpin,
+            verify_cache,
+            get_rejections,
+            clear_rejections,
+            get_pause,
+            pause,
+            pause_until,
+            snooze_current,
+            resume,
             exit,
         ])
         .system_tray(tray::setup())
@@ -0,0 +1,127 @@
+use crate::{app_handle_ext::AppHandleExt, log::LogLevel};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use macros::command;
+use serde::{Deserialize, Serialize};
+use std::{fs::read_to_string, path::PathBuf, time::Duration};
+use tauri::{
+    async_runtime::{spawn, Mutex},
+    AppHandle, Manager,
+};
+use tokio::time::interval;
+use ts_rs::TS;
+
+/// How often the tray's remaining pause time gets refreshed
+const TRAY_REFRESH: Duration = Duration::from_secs(30);
+
+/// Whether the changer is allowed to rotate wallpapers on its own
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub enum PauseState {
+    #[default]
+    Running,
+    /// Paused until resumed
+    Paused,
+    /// Paused until the given time
+    PausedUntil(#[ts(type = "string")] DateTime<Utc>),
+}
+
+impl PauseState {
+    /// Expired pauses count as running
+    pub fn at(self, now: DateTime<Utc>) -> Self {
+        match self {
+            PauseState::PausedUntil(until) if until <= now => PauseState::Running,
+            state => state,
+        }
+    }
+
+    fn tray_title(&self) -> String {
+        match self {
+            PauseState::Running => "Pause rotation".to_string(),
+            PauseState::Paused => "Resume rotation (paused)".to_string(),
+            PauseState::PausedUntil(until) => {
+                let minutes = (*until - Utc::now()).num_minutes() + 1;
+                let remaining = if minutes >= 60 {
+                    format!("{}h {}m", minutes / 60, minutes % 60)
+                } else {
+                    format!("{minutes}m")
+                };
+                format!("Resume rotation ({remaining} left)")
+            }
+        }
+    }
+}
+
+fn state_path(app: &AppHandle) -> PathBuf {
+    app.path_resolver()
+        .app_config_dir()
+        .unwrap()
+        .join("pause.json")
+}
+
+/// Restores the persisted pause state and keeps the tray up to date with it
+pub fn setup_pause(app: AppHandle) {
+    let state = read_to_string(state_path(&app))
+        .ok()
+        .and_then(|json| serde_json::from_str::<PauseState>(&json).ok())
+        .unwrap_or_default()
+        .at(Utc::now());
+    app.manage(Mutex::new(state));
+    spawn(async move {
+        let mut interval = interval(TRAY_REFRESH);
+        loop {
+            interval.tick().await;
+            let state = app.pause_state().await;
+            if let PauseState::PausedUntil(until) = state
+                && until <= Utc::now()
+            {
+                set_pause(&app, PauseState::Running)
+                    .await
+                    .unwrap_or_else(|err| app.log(&err, LogLevel::Error));
+                continue;
+            }
+            let _ = app
+                .tray_handle()
+                .get_item("toggle_pause")
+                .set_title(state.tray_title());
+        }
+    });
+}
+
+/// Updates, persists and announces the pause state
+pub async fn set_pause(app: &AppHandle, state: PauseState) -> Result<()> {
+    *app.state::<Mutex<PauseState>>().lock().await = state.clone();
+    tokio::fs::write(state_path(app), serde_json::to_vec_pretty(&state)?).await?;
+    app.tray_handle()
+        .get_item("toggle_pause")
+        .set_title(state.tray_title())?;
+    app.emit_all("pause_changed", &state)?;
+    Ok(())
+}
+
+#[command]
+pub async fn get_pause(app: AppHandle) -> Result<PauseState> {
+    Ok(app.pause_state().await)
+}
+
+#[command]
+pub async fn pause(app: AppHandle) -> Result<()> {
+    set_pause(&app, PauseState::Paused).await
+}
+
+#[command]
+pub async fn pause_until(app: AppHandle, until: DateTime<Utc>) -> Result<()> {
+    set_pause(&app, PauseState::PausedUntil(until).at(Utc::now())).await
+}
+
+/// Keeps the current wallpaper for `duration` before rotation carries on
+#[command]
+pub async fn snooze_current(app: AppHandle, duration: Duration) -> Result<()> {
+    let until = Utc::now() + chrono::Duration::from_std(duration)?;
+    set_pause(&app, PauseState::PausedUntil(until)).await
+}
+
+#[command]
+pub async fn resume(app: AppHandle) -> Result<()> {
+    set_pause(&app, PauseState::Running).await
+}
@@ -0,0 +1,156 @@
+use crate::{
+    app_handle_ext::AppHandleExt,
+    cache::{cache_limit, cached_bytes, cached_file},
+    log::LogLevel,
+    queue::get_queue,
+    wallpaper_changer::download_wallpaper,
+};
+use anyhow::{anyhow, Result};
+use futures::{stream, StreamExt};
+use macros::command;
+use serde::Serialize;
+use std::fs;
+use tauri::{
+    async_runtime::{spawn, JoinHandle, Mutex},
+    AppHandle, Manager,
+};
+use ts_rs::TS;
+
+/// How far along the prefetch worker is. Emitted as `prefetch_progress` whenever it changes
+#[derive(Serialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct PrefetchProgress {
+    /// Downloads finished so far, including failed ones
+    pub done: usize,
+    pub failed: usize,
+    /// Queued wallpapers which weren't cached when the worker started
+    pub total: usize,
+    #[ts(type = "number")]
+    pub bytes: u64,
+    /// False once the worker has finished or been cancelled
+    pub running: bool,
+}
+
+impl PrefetchProgress {
+    fn tray_title(&self) -> String {
+        if self.running {
+            format!("Cancel prefetch ({}/{})", self.done, self.total)
+        } else {
+            "Not prefetching".to_string()
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Prefetch {
+    worker: Option<JoinHandle<()>>,
+    progress: PrefetchProgress,
+}
+
+pub type PrefetchState = Mutex<Prefetch>;
+
+pub fn setup_prefetch(app: AppHandle) -> bool {
+    app.manage(PrefetchState::default())
+}
+
+/// Changes the progress, and lets the UI and tray know
+async fn update_progress(app: &AppHandle, change: impl FnOnce(&mut PrefetchProgress)) {
+    let state = app.state::<PrefetchState>();
+    let mut prefetch = state.lock().await;
+    change(&mut prefetch.progress);
+    let progress = &prefetch.progress;
+    app.tray_handle()
+        .get_item("cancel_prefetch")
+        .set_title(progress.tray_title())
+        .and_then(|_| {
+            app.tray_handle()
+                .get_item("cancel_prefetch")
+                .set_enabled(progress.running)
+        })
+        .and_then(|_| app.emit_all("prefetch_progress", progress))
+        .unwrap_or_else(|err| app.log(&err, LogLevel::Error));
+}
+
+/// Downloads queued wallpapers which aren't cached yet, a few at a time.
+/// Failed downloads are counted and skipped, rather than stopping the rest
+async fn run(app: &AppHandle) -> Result<()> {
+    let concurrency = app.get_config().await.prefetch_concurrency.max(1);
+    let mut pending = Vec::new();
+    for wallpaper in get_queue(app.app_handle()).await.map_err(|e| anyhow!(e))? {
+        if cached_file(app, &wallpaper.id).await?.is_none() {
+            pending.push(wallpaper);
+        }
+    }
+    let total = pending.len();
+    update_progress(app, |progress| {
+        *progress = PrefetchProgress {
+            total,
+            running: true,
+            ..Default::default()
+        }
+    })
+    .await;
+
+    let mut downloads = stream::iter(pending)
+        .map(|wallpaper| {
+            let app = app.app_handle();
+            async move {
+                // The cache is only filled up to its size, the rest is downloaded when needed
+                if cached_bytes(&app).await? >= cache_limit(&app).await {
+                    return Ok(0);
+                }
+                let path = download_wallpaper(&app, &wallpaper).await?;
+                Ok::<_, anyhow::Error>(fs::metadata(path)?.len())
+            }
+        })
+        .buffer_unordered(concurrency);
+    while let Some(result) = downloads.next().await {
+        if let Err(err) = &result {
+            app.log(err, LogLevel::Debug);
+        }
+        update_progress(app, |progress| {
+            progress.done += 1;
+            match result {
+                Ok(bytes) => progress.bytes += bytes,
+                Err(_) => progress.failed += 1,
+            }
+        })
+        .await;
+    }
+
+    let failed = app.state::<PrefetchState>().lock().await.progress.failed;
+    if failed > 0 {
+        app.log(
+            &format!("{failed} of {total} wallpapers couldn't be prefetched"),
+            LogLevel::Info,
+        );
+    }
+    Ok(())
+}
+
+/// Starts prefetching the queue in the background, replacing a prefetch in progress
+pub async fn start_prefetch(app: &AppHandle) {
+    let state = app.state::<PrefetchState>();
+    let mut prefetch = state.lock().await;
+    if let Some(worker) = prefetch.worker.take() {
+        worker.abort();
+    }
+    let app = app.app_handle();
+    prefetch.worker = Some(spawn(async move {
+        run(&app)
+            .await
+            .unwrap_or_else(|err| app.log(&err, LogLevel::Error));
+        update_progress(&app, |progress| progress.running = false).await;
+    }));
+}
+
+/// Stops the prefetch worker. Downloads in progress are abandoned
+#[command]
+pub async fn cancel_prefetch(app: AppHandle) -> Result<()> {
+    let worker = app.state::<PrefetchState>().lock().await.worker.take();
+    if let Some(worker) = worker {
+        worker.abort();
+        update_progress(&app, |progress| progress.running = false).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,339 @@
+use crate::{
+    app_handle_ext::AppHandleExt,
+    cache,
+    displays::{outputs, Output},
+    effects::{apply_effects, parse_color},
+    queue::current_wallpaper,
+    wallpaper_changer::hash_url,
+};
+use anyhow::{anyhow, Result};
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage,
+};
+use reddw_source_plugin::Wallpaper;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::{async_runtime::spawn_blocking, AppHandle};
+use ts_rs::TS;
+
+/// How a wallpaper is made to match the screen's resolution
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub enum ScalingMode {
+    /// Hands the original over untouched, leaving scaling to the setter
+    #[default]
+    Original,
+    /// Scales to cover the screen, cropping the overflow evenly
+    Fill,
+    /// Scales to fit inside the screen, filling the rest with the background colour
+    Fit,
+    /// Keeps the original size, cropping or filling around it
+    Center,
+    /// Scales to the screen's size, ignoring the aspect ratio
+    Stretch,
+    /// Like fill, but crops to the busiest part of the image rather than the middle
+    SmartCrop,
+}
+
+impl ScalingMode {
+    fn key(&self) -> &'static str {
+        match self {
+            ScalingMode::Original => "original",
+            ScalingMode::Fill => "fill",
+            ScalingMode::Fit => "fit",
+            ScalingMode::Center => "center",
+            ScalingMode::Stretch => "stretch",
+            ScalingMode::SmartCrop => "smartcrop",
+        }
+    }
+}
+
+/// Width the image is shrunk to when looking for its busiest part
+const SMART_CROP_SAMPLE: u32 = 256;
+
+/// Offset at which a window of `window` samples covers the most detail
+fn busiest_offset(energy: &[u64], window: usize) -> usize {
+    let window = window.max(1);
+    if window >= energy.len() {
+        return 0;
+    }
+    let mut sum = energy[..window].iter().sum::<u64>();
+    let (mut best, mut best_sum) = (0, sum);
+    for start in 1..=energy.len() - window {
+        sum = sum + energy[start + window - 1] - energy[start - 1];
+        if sum > best_sum {
+            (best, best_sum) = (start, sum);
+        }
+    }
+    best
+}
+
+/// Crops to the target's aspect ratio around the area with the most edges, then scales
+fn smart_crop(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (image_width, image_height) = image.dimensions();
+    let scale = (width as f64 / image_width as f64).max(height as f64 / image_height as f64);
+    let crop_width = ((width as f64 / scale).round() as u32).clamp(1, image_width);
+    let crop_height = ((height as f64 / scale).round() as u32).clamp(1, image_height);
+
+    let sample_scale = SMART_CROP_SAMPLE as f64 / image_width.max(image_height) as f64;
+    let sample = image
+        .resize(
+            ((image_width as f64 * sample_scale) as u32).max(1),
+            ((image_height as f64 * sample_scale) as u32).max(1),
+            FilterType::Triangle,
+        )
+        .to_luma8();
+    let (sample_width, sample_height) = sample.dimensions();
+    let mut columns = vec![0u64; sample_width as usize];
+    let mut rows = vec![0u64; sample_height as usize];
+    for y in 1..sample_height {
+        for x in 1..sample_width {
+            let pixel = sample.get_pixel(x, y)[0] as i64;
+            let gradient = (pixel - sample.get_pixel(x - 1, y)[0] as i64).unsigned_abs()
+                + (pixel - sample.get_pixel(x, y - 1)[0] as i64).unsigned_abs();
+            columns[x as usize] += gradient;
+            rows[y as usize] += gradient;
+        }
+    }
+    let x = busiest_offset(
+        &columns,
+        (crop_width as f64 * sample_scale).round() as usize,
+    ) as f64
+        / sample_scale;
+    let y = busiest_offset(&rows, (crop_height as f64 * sample_scale).round() as usize) as f64
+        / sample_scale;
+    let x = (x as u32).min(image_width - crop_width);
+    let y = (y as u32).min(image_height - crop_height);
+    image
+        .crop_imm(x, y, crop_width, crop_height)
+        .resize_exact(width, height, FilterType::Lanczos3)
+}
+
+/// Places the image in the middle of a background-coloured canvas of the given size
+fn on_canvas(image: &DynamicImage, width: u32, height: u32, background: Rgba<u8>) -> DynamicImage {
+    let mut canvas = RgbaImage::from_pixel(width, height, background);
+    let x = (width as i64 - image.width() as i64) / 2;
+    let y = (height as i64 - image.height() as i64) / 2;
+    imageops::overlay(&mut canvas, &image.to_rgba8(), x, y);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+fn scale(
+    image: &DynamicImage,
+    mode: ScalingMode,
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+) -> DynamicImage {
+    match mode {
+        ScalingMode::Original => image.clone(),
+        ScalingMode::Fill => image.resize_to_fill(width, height, FilterType::Lanczos3),
+        ScalingMode::Fit => on_canvas(
+            &image.resize(width, height, FilterType::Lanczos3),
+            width,
+            height,
+            background,
+        ),
+        ScalingMode::Center => on_canvas(image, width, height, background),
+        ScalingMode::Stretch => image.resize_exact(width, height, FilterType::Lanczos3),
+        ScalingMode::SmartCrop => smart_crop(image, width, height),
+    }
+}
+
+/// Where the processed version of `original` is kept - next to it, named after the settings
+fn derived_path(original: &Path, key: &str) -> PathBuf {
+    let stem = original
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = original
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or("png".to_string());
+    original.with_file_name(format!("{stem}.{key}.{extension}"))
+}
+
+/// The resolution to process for. Without a specific output, the largest connected one is used
+async fn target_resolution(app: &AppHandle, output: Option<&Output>) -> Option<(u32, u32)> {
+    match output {
+        Some(output) => Some((output.width, output.height)),
+        None => outputs(app)
+            .await
+            .into_iter()
+            .map(|(output, _)| (output.width, output.height))
+            .max_by_key(|(width, height)| width * height),
+    }
+}
+
+/// Path to a copy of the wallpaper scaled for `output` with the configured effects applied,
+/// creating it if it isn't cached yet. Returns the original when there's nothing to do
+pub async fn process_wallpaper(
+    app: &AppHandle,
+    wallpaper: &Wallpaper,
+    original: &Path,
+    output: Option<&Output>,
+) -> Result<PathBuf> {
+    let config = app.get_config().await;
+    let effects = config.effects;
+    // Scaling needs the screen's resolution, so it's skipped when that's unknown
+    let scaling = match config.scaling {
+        ScalingMode::Original => None,
+        mode => target_resolution(app, output)
+            .await
+            .map(|(width, height)| (mode, width, height)),
+    };
+    if scaling.is_none() && effects.is_empty() {
+        return Ok(original.to_path_buf());
+    }
+    let background_hex = config.background_color.as_deref().unwrap_or("#000000");
+    let background = parse_color(background_hex)?;
+    let mut key = match scaling {
+        Some((mode, width, height)) => format!(
+            "{}-{width}x{height}-{}",
+            mode.key(),
+            background_hex.trim_start_matches('#')
+        ),
+        None => ScalingMode::Original.key().to_string(),
+    };
+    if !effects.is_empty() {
+        // The name is part of the chain's output when it's overlaid
+        let chain = serde_json::to_string(&(&effects, &wallpaper.name))?;
+        key = format!("{key}-fx{}", hash_url(&chain));
+    }
+    let derived = derived_path(original, &key);
+    if derived.exists() {
+        cache::touch_file(app, &derived).await?;
+        return Ok(derived);
+    }
+    let original = original.to_path_buf();
+    let derived_clone = derived.clone();
+    let name = wallpaper.name.clone();
+    spawn_blocking(move || {
+        let mut image = image::open(&original)?;
+        if let Some((mode, width, height)) = scaling {
+            image = scale(&image, mode, width, height, background);
+        }
+        let processed = apply_effects(image, &effects, name.as_deref())?;
+        let format = ImageFormat::from_path(&original).unwrap_or(ImageFormat::Png);
+        // JPEG can't store transparency
+        let processed = match format {
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(processed.to_rgb8()),
+            _ => processed,
+        };
+        processed.save_with_format(&derived_clone, format)?;
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+    // Derived files share their original's hash, which ties them to it in the cache
+    let hash = cache::file_hash(&derived).ok_or(anyhow!(
+        "Can't tell which content {} was derived from",
+        derived.display()
+    ))?;
+    cache::record_file(app, &hash, &derived, true).await?;
+    let current = current_wallpaper(app).await?.map(|w| w.id);
+    cache::make_room(app, 0, current.as_deref()).await?;
+    Ok(derived)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: Rgba<u8> = Rgba([1, 2, 3, 255]);
+
+    fn blank(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([255; 4])))
+    }
+
+    #[test]
+    fn busiest_offset_finds_the_densest_window() {
+        assert_eq!(busiest_offset(&[0, 0, 5, 5, 0, 9], 2), 2);
+        assert_eq!(busiest_offset(&[0, 0, 5, 5, 0, 9], 1), 5);
+        // Ties go to the first window
+        assert_eq!(busiest_offset(&[1, 1, 1, 1], 2), 0);
+    }
+
+    #[test]
+    fn busiest_offset_with_a_window_too_big_or_empty() {
+        assert_eq!(busiest_offset(&[1, 2, 3], 3), 0);
+        assert_eq!(busiest_offset(&[1, 2, 3], 10), 0);
+        // An empty window is treated as one sample wide
+        assert_eq!(busiest_offset(&[1, 2, 3], 0), 2);
+        assert_eq!(busiest_offset(&[], 0), 0);
+    }
+
+    #[test]
+    fn smart_crop_keeps_the_detailed_side() {
+        // Plain on the left, striped on the right
+        let mut image = RgbaImage::from_pixel(400, 100, Rgba([255; 4]));
+        for x in (300..400).filter(|x| x / 4 % 2 == 0) {
+            for y in 0..100 {
+                image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+        let cropped = smart_crop(&DynamicImage::ImageRgba8(image), 100, 100);
+        assert_eq!(cropped.dimensions(), (100, 100));
+        // The stripes survive, so the crop came from the right
+        assert!(cropped.to_luma8().pixels().any(|pixel| pixel[0] < 128));
+        // The plain side alone would've been all white
+        let plain = smart_crop(&blank(400, 100), 100, 100);
+        assert!(plain.to_luma8().pixels().all(|pixel| pixel[0] > 128));
+    }
+
+    #[test]
+    fn on_canvas_centers() {
+        let canvas = on_canvas(&blank(2, 2), 4, 4, BACKGROUND);
+        assert_eq!(canvas.dimensions(), (4, 4));
+        assert_eq!(canvas.get_pixel(0, 0), BACKGROUND);
+        assert_eq!(canvas.get_pixel(1, 1), Rgba([255; 4]));
+        assert_eq!(canvas.get_pixel(2, 2), Rgba([255; 4]));
+        assert_eq!(canvas.get_pixel(3, 3), BACKGROUND);
+        // Larger images get cropped evenly
+        let cropped = on_canvas(&blank(8, 8), 4, 4, BACKGROUND);
+        assert_eq!(cropped.get_pixel(0, 0), Rgba([255; 4]));
+    }
+
+    #[test]
+    fn scale_sizes() {
+        let image = blank(400, 200);
+        for mode in [
+            ScalingMode::Fill,
+            ScalingMode::Fit,
+            ScalingMode::Center,
+            ScalingMode::Stretch,
+            ScalingMode::SmartCrop,
+        ] {
+            assert_eq!(
+                scale(&image, mode, 100, 100, BACKGROUND).dimensions(),
+                (100, 100),
+                "{mode:?}"
+            );
+        }
+        assert_eq!(
+            scale(&image, ScalingMode::Original, 100, 100, BACKGROUND).dimensions(),
+            (400, 200)
+        );
+    }
+
+    #[test]
+    fn fit_letterboxes() {
+        let fitted = scale(&blank(400, 200), ScalingMode::Fit, 100, 100, BACKGROUND);
+        // 100x50 in the middle, with background above and below
+        assert_eq!(fitted.get_pixel(50, 10), BACKGROUND);
+        assert!(fitted.get_pixel(50, 50)[0] > 250);
+        assert_eq!(fitted.get_pixel(50, 90), BACKGROUND);
+    }
+
+    #[test]
+    fn derived_path_sits_next_to_the_original() {
+        assert_eq!(
+            derived_path(Path::new("/cache/abc.jpg"), "fill-1920x1080-000000"),
+            Path::new("/cache/abc.fill-1920x1080-000000.jpg")
+        );
+        assert_eq!(
+            cache::file_hash(&derived_path(Path::new("/cache/abc.jpg"), "fit")).as_deref(),
+            Some("abc")
+        );
+    }
+}
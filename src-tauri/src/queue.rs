@@ -1,18 +1,26 @@
 use crate::app_handle_ext::AppHandleExt;
+use crate::cache;
+use crate::history::HistoryCursor;
+use crate::log::LogLevel;
+use crate::prefetch::start_prefetch;
+use crate::retry::Backoff;
 use crate::source_host::SourcePlugins;
-use crate::wallpaper_changer::download_wallpaper;
 use ::futures::future::join_all;
 use anyhow::{anyhow, Result};
 use macros::command;
 use reddw_source_plugin::Wallpaper;
 use sqlx::migrate::MigrateDatabase;
 use sqlx::{migrate, query, query_as, Pool, Sqlite};
-use std::fs::{self, read_dir};
+use std::collections::HashMap;
+use std::fs;
 use tauri::async_runtime::{spawn, Mutex};
 use tauri::{AppHandle, Manager};
 
 pub type DB = Pool<Sqlite>;
 
+/// Version of the migration which started naming cached files after their content
+const CONTENT_ADDRESSED_CACHE: i64 = 20261018150000;
+
 pub async fn manage_queue(app: &AppHandle) -> Result<()> {
     let cache_dir = app.get_config().await.cache_dir;
     if !cache_dir.exists() {
@@ -24,8 +32,28 @@ pub async fn manage_queue(app: &AppHandle) -> Result<()> {
         Sqlite::create_database(db_url).await?;
     }
     let db: DB = { sqlx::SqlitePool::connect(db_url).await? };
+    // Fresh databases don't have the migrations table yet
+    let content_addressed = sqlx::query("select version from _sqlx_migrations where version = ?")
+        .bind(CONTENT_ADDRESSED_CACHE)
+        .fetch_optional(&db)
+        .await
+        .is_ok_and(|row| row.is_some());
     migrate!().run(&db).await?;
     app.manage(Mutex::new(db));
+    // Moving to a content addressed cache drops the old index, so the files in the cache
+    // are taken in again under their hashes
+    if !content_addressed {
+        let app = app.app_handle();
+        spawn(async move {
+            let verified = match current_wallpaper(&app).await {
+                Ok(current) => cache::verify(&app, current.map(|w| w.id).as_deref()).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = verified {
+                app.log(&err, LogLevel::Error);
+            }
+        });
+    }
     Ok(())
 }
 
@@ -38,36 +66,122 @@ pub async fn get_queue(app: tauri::AppHandle) -> Result<Vec<Wallpaper>> {
     Ok(queue)
 }
 
+/// The wallpaper currently on display - the newest history entry unless the user stepped back
+pub async fn current_wallpaper(app: &AppHandle) -> Result<Option<Wallpaper>> {
+    let cursor = *app.state::<HistoryCursor>().lock().await;
+    history_entry(app, cursor).await
+}
+
+/// The history entry `offset` steps back from the newest one
+pub async fn history_entry(app: &AppHandle, offset: i64) -> Result<Option<Wallpaper>> {
+    history_at(&app.db().await, offset).await
+}
+
+async fn history_at(db: &DB, offset: i64) -> Result<Option<Wallpaper>> {
+    let wallpaper = query_as!(
+        Wallpaper,
+        "---sql
+        select * from queue
+        where was_set = 1 and id not in (select id from retired)
+        order by date desc
+        limit 1 offset ?",
+        offset
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(wallpaper)
+}
+
+/// Moves a wallpaper into the history
+pub async fn record_set(db: &DB, id: &str) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    query!(
+        "---sql
+        update queue set was_set = 1, date = $1 where id = $2",
+        now,
+        id
+    )
+    .execute(db)
+    .await?;
+    query!("delete from retired where id = ?", id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Marks a wallpaper shown on another output than the first as set. It stays in the queue,
+/// so it's neither picked nor offered by its plugin again, but isn't a history entry of its own
+pub async fn retire(db: &DB, id: &str) -> Result<()> {
+    let now = chrono::Utc::now().naive_utc();
+    query!(
+        "---sql
+        update queue set was_set = 1, date = $1 where id = $2",
+        now,
+        id
+    )
+    .execute(db)
+    .await?;
+    query!("insert or ignore into retired (id) values (?)", id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
 pub async fn trim_queue(app: &tauri::AppHandle) -> Result<()> {
     let config = app.get_config().await.clone();
-    // Remove history entries except for the newest x
+    trim_history(&app.db().await, config.history_amount).await
+}
+
+/// Removes history entries except for the newest `keep`, keeping favorites regardless.
+/// Retired wallpapers go along with the rotations they were shown in
+async fn trim_history(db: &DB, keep: i32) -> Result<()> {
     query!(
         "---sql
         delete from queue 
         where id in 
         (select id from queue 
-            where was_set = 1 
-            order by date
+            where was_set = 1 and id not in (select id from favorites)
+                and id not in (select id from retired)
+            order by date desc
             limit -1 offset ?)
         ;
         ",
-        config.history_amount
+        keep
     )
-    .execute(&app.db().await)
+    .execute(db)
     .await?;
+    query!(
+        "---sql
+        delete from queue
+        where id in (select id from retired)
+            and id not in (select id from favorites)
+            and date < (
+                select min(date) from queue
+                where was_set = 1 and id not in (select id from retired)
+            )"
+    )
+    .execute(db)
+    .await?;
+    query!("delete from retired where id not in (select id from queue)")
+        .execute(db)
+        .await?;
     Ok(())
 }
 
 #[command]
 pub async fn cache_queue(app: AppHandle) -> Result<usize> {
     trim_queue(&app).await?;
-    let plugins = app.state::<SourcePlugins>();
-    let mut plugins = plugins.lock().await;
-    let wallpapers = join_all(plugins.values_mut().map(|plugin| {
+    let names = app
+        .state::<SourcePlugins>()
+        .lock()
+        .await
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    let wallpapers = join_all(names.into_iter().map(|name| {
         let app = app.app_handle();
         async move {
             let config = app.get_config().await;
-            let name = plugin.name.clone();
             let mut wallpapers = Vec::new();
             for instance in config.sources.keys().filter_map(|key| {
                 let (plugin, instance) = key.split_once("_")?;
@@ -76,13 +190,8 @@ pub async fn cache_queue(app: AppHandle) -> Result<usize> {
                 }
                 Some(instance)
             }) {
-                let ids = get_ids_from_source(&app, &plugin.name).await?;
-                wallpapers.extend(
-                    plugin
-                        .get_wallpapers(instance.to_string(), ids)
-                        .await
-                        .map_err(|err| anyhow!(err.to_string()))?,
-                );
+                let ids = get_ids_from_source(&app, &name).await?;
+                wallpapers.extend(fetch_wallpapers(&app, &name, instance, ids).await?);
             }
             Ok(wallpapers)
         }
@@ -91,26 +200,62 @@ pub async fn cache_queue(app: AppHandle) -> Result<usize> {
     .into_iter()
     .collect::<Result<Vec<_>>>()?;
     let wallpapers = wallpapers.into_iter().flat_map(|w| w).collect::<Vec<_>>();
-    spawn(download_queue(app.app_handle()));
+    start_prefetch(&app).await;
     Ok(wallpapers.len())
 }
 
-pub async fn download_queue(app: tauri::AppHandle) -> Result<()> {
-    let queue = get_queue(app.app_handle()).await.map_err(|e| anyhow!(e))?;
-    for wallpaper in queue {
-        let app_clone = app.app_handle();
-
-        let config = app_clone.get_config().await;
-        // If the folder isn't full of photos
-        if read_dir(config.cache_dir)?
-            .filter_map(|f| Some(f.ok()?.metadata().ok()?.len()))
-            .sum::<u64>()
-            < (config.cache_size * 1024.0 * 1024.0).round() as u64
-        {
-            download_wallpaper(&app_clone, wallpaper.data_url).await?;
+/// Asks a plugin instance for wallpapers other than `ids`, retrying with backoff.
+/// The plugins are only locked during each attempt, not while waiting to retry
+pub async fn fetch_wallpapers(
+    app: &AppHandle,
+    plugin_name: &str,
+    instance: &str,
+    ids: Vec<String>,
+) -> Result<Vec<Wallpaper>> {
+    let mut backoff = Backoff::new(
+        app,
+        format!("Getting wallpapers from {plugin_name}_{instance}"),
+    )
+    .await;
+    loop {
+        let attempt = {
+            let plugins = app.state::<SourcePlugins>();
+            let mut plugins = plugins.lock().await;
+            let plugin = plugins
+                .get_mut(plugin_name)
+                .ok_or(anyhow!("Plugin {plugin_name} not found"))?;
+            match plugin
+                .get_wallpapers(instance.to_string(), ids.clone())
+                .await
+            {
+                Ok(wallpapers) => Ok((
+                    wallpapers,
+                    plugin.take_warnings(instance.to_string()).await,
+                )),
+                Err(err) => Err(anyhow!(err.to_string())),
+            }
+        };
+        match attempt {
+            Ok((wallpapers, warnings)) => {
+                match warnings {
+                    Ok(warnings) => {
+                        for warning in warnings {
+                            app.log(
+                                &format!("{plugin_name}_{instance}: {warning}"),
+                                LogLevel::Info,
+                            );
+                        }
+                    }
+                    Err(err) => app.log(
+                        &format!("Couldn't get warnings from {plugin_name}_{instance}: {err}"),
+                        LogLevel::Debug,
+                    ),
+                }
+                return Ok(wallpapers);
+            }
+            Err(err) => backoff.retry(err).await?,
         }
     }
-    Result::<()>::Ok(())
 }
 
 pub async fn get_ids_from_source(
@@ -118,18 +263,177 @@ pub async fn get_ids_from_source(
     plugin: &String,
 ) -> Result<Vec<String>, anyhow::Error> {
     let source = format!("{plugin}_%");
-    let vec = query!("select id from queue where source like ?", source)
-        .fetch_all(&app.db().await)
-        .await?
-        .into_iter();
+    // Banned and rejected wallpapers are always excluded, even once they're gone from the queue
+    let vec = query!(
+        "---sql
+        select id from queue where source like $1
+        union
+        select id from bans where source like $1
+        union
+        select id from rejections where source like $1",
+        source
+    )
+    .fetch_all(&app.db().await)
+    .await?
+    .into_iter();
     Ok(vec.map(|rec| rec.id).collect())
 }
 
+/// Number of wallpapers waiting to be set, per source
+pub async fn queued_counts(app: &AppHandle) -> Result<HashMap<String, i64>> {
+    let counts = query!(
+        r#"select source, count(*) as "count!: i64" from queue where was_set = 0 group by source"#
+    )
+    .fetch_all(&app.db().await)
+    .await?
+    .into_iter()
+    .map(|rec| (rec.source, rec.count))
+    .collect();
+    Ok(counts)
+}
+
 #[command]
 pub async fn refresh_source_queue(app: AppHandle, source: String) -> Result<()> {
     let db = app.db().await;
-    query!("delete from queue where source = ?", source)
-        .execute(&db)
-        .await?;
+    query!(
+        "delete from queue where source = ? and id not in (select id from favorites)",
+        source
+    )
+    .execute(&db)
+    .await?;
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    pub(crate) async fn database() -> DB {
+        // Every connection to an in-memory database gets a database of its own
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        migrate!().run(&db).await.unwrap();
+        db
+    }
+
+    /// Queues a wallpaper dated noon on the given day of October 2026
+    pub(crate) async fn insert(db: &DB, id: &str, day: u32, was_set: bool) {
+        let date = NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        sqlx::query(
+            "insert into queue (id, data_url, date, source, was_set) values ($1, '', $2, 'Test_test', $3)",
+        )
+        .bind(id)
+        .bind(date)
+        .bind(was_set)
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    async fn remaining(db: &DB) -> Vec<String> {
+        sqlx::query_scalar("select id from queue order by date")
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn trim_keeps_the_newest_history() {
+        let db = database().await;
+        insert(&db, "oldest", 1, true).await;
+        insert(&db, "older", 2, true).await;
+        insert(&db, "newer", 3, true).await;
+        insert(&db, "newest", 4, true).await;
+        insert(&db, "queued", 5, false).await;
+        trim_history(&db, 2).await.unwrap();
+        // Wallpapers which haven't been set yet aren't history
+        assert_eq!(remaining(&db).await, ["newer", "newest", "queued"]);
+    }
+
+    #[tokio::test]
+    async fn trim_keeps_favorites() {
+        let db = database().await;
+        insert(&db, "favorite", 1, true).await;
+        insert(&db, "older", 2, true).await;
+        insert(&db, "newest", 3, true).await;
+        sqlx::query("insert into favorites (id, date) values ('favorite', '2026-10-01 12:00:00')")
+            .execute(&db)
+            .await
+            .unwrap();
+        trim_history(&db, 1).await.unwrap();
+        assert_eq!(remaining(&db).await, ["favorite", "newest"]);
+    }
+
+    async fn retire_in_sql(db: &DB, id: &str) {
+        sqlx::query("insert into retired (id) values (?)")
+            .bind(id)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    async fn history_id(db: &DB, offset: i64) -> Option<String> {
+        history_at(db, offset).await.unwrap().map(|w| w.id)
+    }
+
+    #[tokio::test]
+    async fn two_outputs_make_one_history_entry() {
+        let db = database().await;
+        insert(&db, "previous", 1, true).await;
+        insert(&db, "left", 2, false).await;
+        insert(&db, "right", 2, false).await;
+        record_set(&db, "left").await.unwrap();
+        retire(&db, "right").await.unwrap();
+        assert_eq!(history_id(&db, 0).await.as_deref(), Some("left"));
+        assert_eq!(history_id(&db, 1).await.as_deref(), Some("previous"));
+        assert_eq!(history_id(&db, 2).await, None);
+        // Both stay queued, so their plugins don't offer them again, but neither can be picked
+        let mut remaining = remaining(&db).await;
+        remaining.sort();
+        assert_eq!(remaining, ["left", "previous", "right"]);
+        let unset: i64 = sqlx::query_scalar("select count(*) from queue where was_set = 0")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(unset, 0);
+    }
+
+    #[tokio::test]
+    async fn setting_a_retired_wallpaper_makes_it_history() {
+        let db = database().await;
+        insert(&db, "right", 1, false).await;
+        retire(&db, "right").await.unwrap();
+        assert_eq!(history_id(&db, 0).await, None);
+        record_set(&db, "right").await.unwrap();
+        assert_eq!(history_id(&db, 0).await.as_deref(), Some("right"));
+    }
+
+    #[tokio::test]
+    async fn trim_drops_retired_with_their_rotation() {
+        let db = database().await;
+        insert(&db, "old", 1, true).await;
+        insert(&db, "old-right", 1, true).await;
+        retire_in_sql(&db, "old-right").await;
+        insert(&db, "new", 2, true).await;
+        insert(&db, "new-right", 2, true).await;
+        retire_in_sql(&db, "new-right").await;
+        // Retired wallpapers don't count towards the history's size
+        trim_history(&db, 1).await.unwrap();
+        let mut remaining = remaining(&db).await;
+        remaining.sort();
+        assert_eq!(remaining, ["new", "new-right"]);
+        let retired: Vec<String> = sqlx::query_scalar("select id from retired")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(retired, ["new-right"]);
+    }
+}
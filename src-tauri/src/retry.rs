@@ -0,0 +1,209 @@
+use crate::{app_handle_ext::AppHandleExt, log::LogLevel};
+use anyhow::Result;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, io::ErrorKind, time::Duration};
+use tauri::AppHandle;
+use tokio::time::sleep;
+use ts_rs::TS;
+
+/// How failed downloads and plugin calls are retried
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one. 1 disables retrying
+    pub max_attempts: u32,
+    /// Delay before the first retry, in milliseconds. Doubles with each retry after it
+    #[ts(type = "number")]
+    pub base_delay_ms: u64,
+    /// Longest delay between two attempts, in milliseconds
+    #[ts(type = "number")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry`, starting from 1. Jittered to between half and all of
+    /// the exponential delay, so that failures which happened together don't retry together
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay_ms
+            .saturating_mul(2u64.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay_ms);
+        let jitter = rand::random::<f64>() * 0.5 + 0.5;
+        Duration::from_millis((exponential as f64 * jitter) as u64)
+    }
+}
+
+/// Marks an error as one which would only happen again, like a file which isn't an image
+#[derive(Debug)]
+pub struct Permanent(pub String);
+
+impl Display for Permanent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Permanent {}
+
+/// Whether trying again might help. Errors of unknown kinds, like the ones plugins return,
+/// can't be told apart and are assumed to be transient
+pub fn is_retryable(err: &anyhow::Error) -> bool {
+    for cause in err.chain() {
+        if cause.is::<Permanent>() {
+            return false;
+        }
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            return match err.status() {
+                Some(status) => {
+                    status.is_server_error()
+                        || status == StatusCode::TOO_MANY_REQUESTS
+                        || status == StatusCode::REQUEST_TIMEOUT
+                }
+                None => err.is_timeout() || err.is_connect() || err.is_request() || err.is_body(),
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                err.kind(),
+                ErrorKind::TimedOut
+                    | ErrorKind::Interrupted
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::ConnectionRefused
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+            );
+        }
+    }
+    true
+}
+
+/// Keeps track of the attempts at one operation. Each failure goes through `retry`,
+/// which decides whether there's another attempt
+pub struct Backoff<'a> {
+    app: &'a AppHandle,
+    policy: RetryPolicy,
+    what: String,
+    attempt: u32,
+}
+
+impl<'a> Backoff<'a> {
+    /// `what` describes the operation in logs
+    pub async fn new(app: &'a AppHandle, what: impl Display) -> Backoff<'a> {
+        Self {
+            app,
+            policy: app.get_config().await.retry,
+            what: what.to_string(),
+            attempt: 0,
+        }
+    }
+
+    /// Waits before the next attempt, or hands the error back if it's not worth retrying
+    pub async fn retry(&mut self, err: anyhow::Error) -> Result<()> {
+        self.attempt += 1;
+        let attempts = self.policy.max_attempts.max(1);
+        if !is_retryable(&err) || self.attempt >= attempts {
+            self.app.log(
+                &format!(
+                    "{} failed (attempt {}/{attempts}), giving up: {err}",
+                    self.what, self.attempt
+                ),
+                LogLevel::Debug,
+            );
+            return Err(err);
+        }
+        let delay = self.policy.delay(self.attempt);
+        self.app.log(
+            &format!(
+                "{} failed (attempt {}/{attempts}), retrying in {:.1}s: {err}",
+                self.what,
+                self.attempt,
+                delay.as_secs_f64()
+            ),
+            LogLevel::Debug,
+        );
+        sleep(delay).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    /// Checks every `delay(retry)` over a number of draws falls within `min..=max` milliseconds
+    fn assert_between(policy: &RetryPolicy, retry: u32, min: u64, max: u64) {
+        for _ in 0..100 {
+            let delay = policy.delay(retry).as_millis() as u64;
+            assert!(
+                (min..=max).contains(&delay),
+                "retry {retry} waited {delay}ms, outside {min}..={max}"
+            );
+        }
+    }
+
+    #[test]
+    fn delay_doubles_with_jitter() {
+        let policy = RetryPolicy::default();
+        assert_between(&policy, 1, 250, 500);
+        assert_between(&policy, 2, 500, 1000);
+        assert_between(&policy, 3, 1000, 2000);
+        assert_between(&policy, 4, 2000, 4000);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = RetryPolicy::default();
+        assert_between(&policy, 7, 15_000, 30_000);
+        assert_between(&policy, 100, 15_000, 30_000);
+        // Doesn't overflow
+        assert_between(&policy, u32::MAX, 15_000, 30_000);
+    }
+
+    #[test]
+    fn delay_without_a_base() {
+        let policy = RetryPolicy {
+            base_delay_ms: 0,
+            ..Default::default()
+        };
+        assert_between(&policy, 3, 0, 0);
+    }
+
+    #[test]
+    fn permanent_errors_stop_retrying() {
+        assert!(!is_retryable(&anyhow!(Permanent(
+            "Not an image".to_string()
+        ))));
+        // Even behind context
+        let wrapped = Err::<(), _>(Permanent("Not an image".to_string()))
+            .context("Downloading")
+            .unwrap_err();
+        assert!(!is_retryable(&wrapped));
+    }
+
+    #[test]
+    fn io_errors_by_kind() {
+        let io = |kind: ErrorKind| anyhow::Error::new(std::io::Error::from(kind));
+        assert!(is_retryable(&io(ErrorKind::TimedOut)));
+        assert!(is_retryable(&io(ErrorKind::ConnectionReset)));
+        assert!(!is_retryable(&io(ErrorKind::NotFound)));
+        assert!(!is_retryable(&io(ErrorKind::PermissionDenied)));
+    }
+
+    #[test]
+    fn unknown_errors_are_retried() {
+        assert!(is_retryable(&anyhow!("The plugin crashed")));
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use ts_rs::TS;
+
+/// A rule describing when wallpapers should change.
+/// An empty `days` list means every day of the week
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub enum ScheduleRule {
+    /// Change every `interval`, starting at `from` and up to `to` (inclusive).
+    /// Intervals shorter than a millisecond only fire at `from`
+    Every {
+        #[ts(type = "{secs: number, nanos: number}")]
+        interval: Duration,
+        #[ts(type = "string")]
+        from: NaiveTime,
+        #[ts(type = "string")]
+        to: NaiveTime,
+        #[serde(default)]
+        #[ts(type = "Array<\"Mon\" | \"Tue\" | \"Wed\" | \"Thu\" | \"Fri\" | \"Sat\" | \"Sun\">")]
+        days: Vec<Weekday>,
+    },
+    /// Change once a day, at `time`
+    At {
+        #[ts(type = "string")]
+        time: NaiveTime,
+        #[serde(default)]
+        #[ts(type = "Array<\"Mon\" | \"Tue\" | \"Wed\" | \"Thu\" | \"Fri\" | \"Sat\" | \"Sun\">")]
+        days: Vec<Weekday>,
+    },
+}
+
+pub type Schedule = Vec<ScheduleRule>;
+
+/// Everything the changer needs in order to know when to fire next
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rotation {
+    pub interval: Duration,
+    /// Takes precedence over `interval` when not empty
+    pub schedule: Schedule,
+}
+
+impl Rotation {
+    /// The first time after `after` at which the wallpaper should change, if ever
+    pub fn next_fire(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        if self.schedule.is_empty() {
+            if self.interval.is_zero() {
+                return None;
+            }
+            return Some(after + chrono::Duration::from_std(self.interval).ok()?);
+        }
+        self.schedule
+            .iter()
+            .filter_map(|rule| rule.next_fire(after))
+            .min()
+    }
+}
+
+impl ScheduleRule {
+    fn days(&self) -> &[Weekday] {
+        match self {
+            ScheduleRule::Every { days, .. } | ScheduleRule::At { days, .. } => days,
+        }
+    }
+
+    fn next_fire(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        // Starting from yesterday catches windows which began then and run past midnight.
+        // A week after today covers every rule, including one which only fires earlier today
+        let yesterday = after.date_naive().pred_opt()?;
+        (0..=8)
+            .filter_map(|offset| yesterday.checked_add_days(Days::new(offset)))
+            .filter(|date| self.days().is_empty() || self.days().contains(&date.weekday()))
+            .find_map(|date| self.fire_on(date, after))
+    }
+
+    /// The first firing of the window starting on `date` which comes after `after`.
+    /// `days` apply to the date a window starts on, even when it runs into the next one
+    fn fire_on(&self, date: NaiveDate, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            ScheduleRule::At { time, .. } => {
+                let time = local(date, *time)?;
+                (time > after).then_some(time)
+            }
+            ScheduleRule::Every {
+                interval, from, to, ..
+            } => {
+                let start = local(date, *from)?;
+                let mut end = local(date, *to)?;
+                // Windows like 22:00-02:00 run past midnight
+                if end < start {
+                    end = end + chrono::Duration::days(1);
+                }
+                if after < start {
+                    return Some(start);
+                }
+                let interval = chrono::Duration::from_std(*interval).ok()?;
+                let interval_ms = interval.num_milliseconds();
+                if interval_ms == 0 {
+                    return None;
+                }
+                let elapsed = (after - start).num_milliseconds() / interval_ms;
+                let next = start + interval * (elapsed as i32 + 1);
+                (next <= end).then_some(next)
+            }
+        }
+    }
+}
+
+fn local(date: NaiveDate, time: NaiveTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date.and_time(time)).earliest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2026-06-01 is a Monday, well clear of any daylight saving change
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        local(
+            NaiveDate::from_ymd_opt(2026, 6, day).unwrap(),
+            NaiveTime::from_hms_opt(hour, minute, 0).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn every(minutes: u64, from: NaiveTime, to: NaiveTime, days: Vec<Weekday>) -> ScheduleRule {
+        ScheduleRule::Every {
+            interval: Duration::from_secs(minutes * 60),
+            from,
+            to,
+            days,
+        }
+    }
+
+    #[test]
+    fn at_fires_later_today_or_tomorrow() {
+        let rule = ScheduleRule::At {
+            time: time(9, 0),
+            days: vec![],
+        };
+        assert_eq!(rule.next_fire(at(1, 8, 0)), Some(at(1, 9, 0)));
+        assert_eq!(rule.next_fire(at(1, 9, 0)), Some(at(2, 9, 0)));
+    }
+
+    #[test]
+    fn every_below_a_millisecond_fires_at_the_start() {
+        let rule = ScheduleRule::Every {
+            interval: Duration::from_micros(500),
+            from: time(9, 0),
+            to: time(17, 0),
+            days: vec![],
+        };
+        assert_eq!(rule.next_fire(at(1, 8, 0)), Some(at(1, 9, 0)));
+        assert_eq!(rule.next_fire(at(1, 10, 0)), Some(at(2, 9, 0)));
+    }
+
+    #[test]
+    fn every_inside_window() {
+        let rule = every(30, time(9, 0), time(17, 0), vec![]);
+        assert_eq!(rule.next_fire(at(1, 10, 10)), Some(at(1, 10, 30)));
+        assert_eq!(rule.next_fire(at(1, 16, 45)), Some(at(1, 17, 0)));
+    }
+
+    #[test]
+    fn every_outside_window() {
+        let rule = every(30, time(9, 0), time(17, 0), vec![]);
+        assert_eq!(rule.next_fire(at(1, 6, 0)), Some(at(1, 9, 0)));
+        assert_eq!(rule.next_fire(at(1, 17, 0)), Some(at(2, 9, 0)));
+    }
+
+    #[test]
+    fn weekdays_skip_the_weekend() {
+        let rule = ScheduleRule::At {
+            time: time(9, 0),
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+        };
+        // Friday after the change, so the next one is on Monday
+        assert_eq!(rule.next_fire(at(5, 10, 0)), Some(at(8, 9, 0)));
+    }
+
+    #[test]
+    fn weekends_skip_the_week() {
+        let rule = every(
+            60,
+            time(10, 0),
+            time(12, 0),
+            vec![Weekday::Sat, Weekday::Sun],
+        );
+        assert_eq!(rule.next_fire(at(2, 11, 0)), Some(at(6, 10, 0)));
+        assert_eq!(rule.next_fire(at(7, 10, 30)), Some(at(7, 11, 0)));
+    }
+
+    #[test]
+    fn overnight_window_before_midnight() {
+        let rule = every(60, time(22, 0), time(2, 0), vec![]);
+        assert_eq!(rule.next_fire(at(1, 21, 0)), Some(at(1, 22, 0)));
+        assert_eq!(rule.next_fire(at(1, 23, 30)), Some(at(2, 0, 0)));
+    }
+
+    #[test]
+    fn overnight_window_after_midnight() {
+        let rule = every(60, time(22, 0), time(2, 0), vec![]);
+        assert_eq!(rule.next_fire(at(2, 1, 0)), Some(at(2, 2, 0)));
+        assert_eq!(rule.next_fire(at(2, 2, 0)), Some(at(2, 22, 0)));
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_the_day_it_starts() {
+        let rule = every(60, time(22, 0), time(2, 0), vec![Weekday::Fri]);
+        // Saturday morning is still Friday night
+        assert_eq!(rule.next_fire(at(6, 0, 30)), Some(at(6, 1, 0)));
+        // Sunday morning isn't
+        assert_eq!(rule.next_fire(at(7, 0, 30)), Some(at(12, 22, 0)));
+    }
+}
@@ -1,10 +1,14 @@
 use crate::{
     app_handle_ext::AppHandleExt,
+    bans::ban_current,
+    favorites,
+    history::{next_wallpaper, previous_wallpaper},
     main_window_setup,
+    pause::{set_pause, PauseState},
+    prefetch::cancel_prefetch,
+    queue::current_wallpaper,
     wallpaper_changer::update_wallpaper,
 };
-use reddw_source_plugin::Wallpaper;
-use sqlx::query_as;
 use tauri::{
     async_runtime, AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu,
 };
@@ -13,7 +17,16 @@ pub fn setup() -> SystemTray {
     let tray = SystemTray::new().with_menu(
         SystemTrayMenu::new()
             .add_item(CustomMenuItem::new("open_info", "Show information"))
+            .add_item(CustomMenuItem::new("toggle_favorite", "Favorite"))
+            .add_item(CustomMenuItem::new("ban_current", "Ban wallpaper"))
             .add_item(CustomMenuItem::new("update_wallpaper", "Update Wallpaper"))
+            .add_item(CustomMenuItem::new(
+                "previous_wallpaper",
+                "Previous wallpaper",
+            ))
+            .add_item(CustomMenuItem::new("next_wallpaper", "Next wallpaper"))
+            .add_item(CustomMenuItem::new("toggle_pause", "Pause rotation"))
+            .add_item(CustomMenuItem::new("cancel_prefetch", "Not prefetching").disabled())
             .add_native_item(tauri::SystemTrayMenuItem::Separator)
             .add_item(CustomMenuItem::new("show", "Show"))
             .add_item(CustomMenuItem::new("quit", "Quit")),
@@ -55,20 +68,64 @@ pub fn event_handler(app: &AppHandle, event: SystemTrayEvent) {
                             .unwrap_or_else(|e| eprintln!("{:#?}", e));
                     });
                 }
+                "previous_wallpaper" => {
+                    let handle = app.app_handle();
+                    async_runtime::spawn(async move {
+                        previous_wallpaper(handle)
+                            .await
+                            .unwrap_or_else(|e| eprintln!("{:#?}", e));
+                    });
+                }
+                "next_wallpaper" => {
+                    let handle = app.app_handle();
+                    async_runtime::spawn(async move {
+                        next_wallpaper(handle)
+                            .await
+                            .unwrap_or_else(|e| eprintln!("{:#?}", e));
+                    });
+                }
+                "toggle_pause" => {
+                    let handle = app.app_handle();
+                    async_runtime::spawn(async move {
+                        let state = match handle.pause_state().await {
+                            PauseState::Running => PauseState::Paused,
+                            _ => PauseState::Running,
+                        };
+                        set_pause(&handle, state)
+                            .await
+                            .unwrap_or_else(|e| eprintln!("{:#?}", e));
+                    });
+                }
+                "cancel_prefetch" => {
+                    let handle = app.app_handle();
+                    async_runtime::spawn(async move {
+                        cancel_prefetch(handle)
+                            .await
+                            .unwrap_or_else(|e| eprintln!("{:#?}", e));
+                    });
+                }
+                "toggle_favorite" => {
+                    let handle = app.app_handle();
+                    async_runtime::spawn(async move {
+                        favorites::toggle_current(&handle)
+                            .await
+                            .unwrap_or_else(|e| eprintln!("{:#?}", e));
+                    });
+                }
+                "ban_current" => {
+                    let handle = app.app_handle();
+                    async_runtime::spawn(async move {
+                        ban_current(handle)
+                            .await
+                            .unwrap_or_else(|e| eprintln!("{:#?}", e));
+                    });
+                }
                 "open_info" => {
                     let app_clone = app.app_handle();
                     async_runtime::spawn(async move {
-                        let dbconn = app_clone.db().await;
-                        let info_url = query_as!(
-                            Wallpaper,
-                            "---sql
-                            select * from queue 
-                            where was_set = 1
-                            order by date desc",
-                        )
-                        .fetch_optional(&dbconn)
-                        .await?
-                        .and_then(|a| a.info_url);
+                        let info_url = current_wallpaper(&app_clone)
+                            .await?
+                            .and_then(|a| a.info_url);
                         if let Some(info_url) = info_url {
                             open::that(&info_url).unwrap_or_else(|e| eprintln!("{:#?}", e));
                         }
@@ -1,44 +1,76 @@
+use crate::app_config::SourceSelection;
 use crate::app_handle_ext::AppHandleExt;
+use crate::cache::{self, PARTIAL_EXTENSION};
+use crate::displays::{outputs, Output};
+use crate::favorites;
+use crate::filters;
+use crate::history::HistoryCursor;
 use crate::log::LogLevel;
-use crate::queue::{get_ids_from_source, trim_queue};
+use crate::lookahead::{refresh_lookahead, take_prepared};
+use crate::pause::PauseState;
+use crate::processing::process_wallpaper;
+use crate::queue::{
+    current_wallpaper, fetch_wallpapers, get_ids_from_source, queued_counts, record_set, retire,
+    trim_queue,
+};
+use crate::retry::{Backoff, Permanent};
+use crate::schedule::Rotation;
 use crate::source_host::SourcePlugins;
 use anyhow::{anyhow, Result};
 use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, Local};
 use data_encoding::BASE32;
+use image::ImageFormat;
 use macros::command;
-use mime_guess::mime::IMAGE;
-use mime_guess::Mime;
-use rand::seq::SliceRandom;
+use rand::distributions::{Distribution, WeightedIndex};
 use reddw_source_plugin::Wallpaper;
+use reqwest::Url;
 use sqlx::{query, query_as};
+use std::collections::HashMap;
 use std::fmt::Display;
-use std::fs::{self, read_dir as read_dir_sync};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
-use std::str::FromStr;
 use std::time::Duration;
 use tauri::{
-    async_runtime::{self, JoinHandle, Sender},
+    async_runtime::{self, Sender},
     AppHandle, Manager,
 };
-use tokio::io::{AsyncReadExt, BufReader};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
-use tokio::{fs::read, time::interval};
+use tokio::{fs::read, select, time::sleep};
 
 pub fn hash_url(this: &(impl Display + ?Sized)) -> String {
     let hash = sha256::digest(this.to_string());
     BASE32.encode(hash.as_bytes())[..7].to_string()
 }
 
-async fn update_wallpaper_internal(app_handle: AppHandle) -> Result<()> {
+/// Picks one of `sources` at random, according to the configured weights
+async fn choose_source<'a>(app_handle: &AppHandle, sources: &[&'a String]) -> Result<&'a String> {
+    if sources.is_empty() {
+        return Err(anyhow!("No sources"));
+    }
     let config = app_handle.get_config().await;
-    let sources = &config.sources.iter().collect::<Vec<_>>();
-    let (key, _) = sources
-        .choose(&mut rand::thread_rng())
-        .ok_or(anyhow!("No sources"))?;
+    let queued = match config.source_selection {
+        SourceSelection::Weighted => HashMap::new(),
+        SourceSelection::QueueSize => queued_counts(app_handle).await?,
+    };
+    let index = WeightedIndex::new(config.source_weights(sources, &queued))
+        .map_err(|err| anyhow!("Couldn't pick a source: {err}"))?;
+    Ok(sources[index.sample(&mut rand::thread_rng())])
+}
+
+/// Picks one of `sources` and takes its newest unset wallpaper other than the `reserved` ones,
+/// asking the source's plugin for more if its queue has run dry
+async fn pick_wallpaper(
+    app_handle: &AppHandle,
+    sources: &[&String],
+    reserved: &[String],
+) -> Result<Wallpaper> {
+    let key = choose_source(app_handle, sources).await?;
     let (plugin_name, instance) = key.split_once("_").ok_or(anyhow!("Invalid sources key"))?;
     let source_str = format!("{key}");
+    let reserved = serde_json::to_string(reserved)?;
     let app_handle_clone = app_handle.clone();
     let get_wp = async || {
         query_as!(
@@ -46,84 +78,86 @@ async fn update_wallpaper_internal(app_handle: AppHandle) -> Result<()> {
             "---sql
             select * from queue 
             where source = $1 and was_set = 0
+                and id not in (select id from bans)
+                and id not in (select id from rejections)
+                and id not in (select value from json_each($2))
             order by date desc",
-            source_str
+            source_str,
+            reserved
         )
         .fetch_optional(&app_handle_clone.db().await)
         .await?
         .ok_or(anyhow!("No wallpapers"))
     };
 
-    let wp = get_wp().await;
-    let wallpaper = {
-        let plugins = app_handle.state::<SourcePlugins>();
-        let mut plugins = plugins.lock().await;
-        let plugin = plugins
-            .get_mut(plugin_name)
-            .ok_or(anyhow!("Plugin {plugin_name} not found"))?;
-        match wp {
-            Ok(wallpaper) => Ok::<Wallpaper, anyhow::Error>(wallpaper),
-            Err(e) => {
-                let e = e as anyhow::Error;
-                let wallpapers = if e.to_string().contains("No wallpapers") {
-                    let ids = get_ids_from_source(&app_handle, &plugin.name).await?;
-                    eprintln!("{ids:#?} {}", ids.len());
-                    plugin
-                        .get_wallpapers(instance.to_string(), ids)
-                        .await
-                        .map_err(|err| anyhow!("{err:#?}"))?
-                } else {
-                    return Err(e);
-                };
-                let db = app_handle.db().await;
-                app_handle.log(
-                    &format!("Got {} wallpapers", wallpapers.len()),
-                    LogLevel::Debug,
-                );
-                for wallpaper in wallpapers {
-                    wallpaper
-                        .db_insert(&db)
-                        .await
-                        .unwrap_or_else(|err| app_handle.log(&err, LogLevel::Error));
-                }
-                let wallpaper = get_wp().await?;
-                Ok(wallpaper)
+    if !app_handle
+        .state::<SourcePlugins>()
+        .lock()
+        .await
+        .contains_key(plugin_name)
+    {
+        return Err(anyhow!("Plugin {plugin_name} not found"));
+    }
+    let wallpaper = match get_wp().await {
+        Ok(wallpaper) => wallpaper,
+        Err(e) if e.to_string().contains("No wallpapers") => {
+            let ids = get_ids_from_source(app_handle, &plugin_name.to_string()).await?;
+            eprintln!("{ids:#?} {}", ids.len());
+            let wallpapers = fetch_wallpapers(app_handle, plugin_name, instance, ids).await?;
+            let db = app_handle.db().await;
+            app_handle.log(
+                &format!("Got {} wallpapers", wallpapers.len()),
+                LogLevel::Debug,
+            );
+            for wallpaper in wallpapers {
+                wallpaper
+                    .db_insert(&db)
+                    .await
+                    .unwrap_or_else(|err| app_handle.log(&err, LogLevel::Error));
             }
+            get_wp().await?
         }
-    }?;
-    trim_queue(&app_handle).await?;
-    let cache_dir = &app_handle.get_config().await.cache_dir;
-    let cache_dir_clone = cache_dir.clone();
-    let downloaded_file = fs::read_dir(cache_dir)?.find_map(|e| {
-        if let Some(name) = (e.as_ref().ok()?.file_name().into_string()).ok()
-            && hash_url(&name) == wallpaper.id
-        {
-            Some(name)
-        } else {
-            None
+        Err(e) => return Err(e),
+    };
+    trim_queue(app_handle).await?;
+    Ok(wallpaper)
+}
+
+/// Path of the wallpaper's file in the cache, downloading it first if needed
+async fn wallpaper_path(app_handle: &AppHandle, wallpaper: &Wallpaper) -> Result<PathBuf> {
+    match cache::cached_file(app_handle, &wallpaper.id).await? {
+        Some(path) => {
+            cache::touch_file(app_handle, &path).await?;
+            Ok(path)
         }
-    });
+        None => download_wallpaper(app_handle, wallpaper).await,
+    }
+}
 
-    let wallpaper_path = if let Some(path) = downloaded_file {
-        Ok(cache_dir_clone.join(path))
-    } else {
-        download_wallpaper(&app_handle, &wallpaper.data_url)
-            .await
-            .map(|p| cache_dir_clone.join(p))
-    }?;
+/// Sets the file as the wallpaper, using the setter command if one is configured.
+/// `output` restricts the change to a single monitor, which requires a setter command
+async fn apply_wallpaper(
+    app_handle: &AppHandle,
+    wallpaper_path: &Path,
+    output: Option<&Output>,
+) -> Result<()> {
+    let config = app_handle.get_config().await;
     if let Some(command) = config.setter_command
         && let Some(wallpaper) = wallpaper_path.to_str()
     {
-        #[cfg(target_family = "unix")] 
-        let (shell, arg, command) = ("bash", "-c", format!("export WP=\"{wallpaper}\" && {command}")); 
+        #[cfg(target_family = "unix")]
+        let (shell, arg) = ("bash", "-c");
         #[cfg(target_family = "windows")]
-        let (shell, arg, command) = ("powershell", "-Script", format!("$env:WP = '{wallpaper}'; {command}"));
-        let command = command.replace("'", "\\'");
-        let mut change_command = Command::new(shell)
-            .arg(arg)
-            .arg(command)
-            .stderr(Stdio::piped())
-            .spawn()?;
+        let (shell, arg) = ("powershell", "-Script");
+        let mut change_command = Command::new(shell);
+        change_command.arg(arg).arg(command).env("WP", wallpaper);
+        if let Some(output) = output {
+            change_command
+                .env("WP_OUTPUT", &output.name)
+                .env("WP_WIDTH", output.width.to_string())
+                .env("WP_HEIGHT", output.height.to_string());
+        }
+        let mut change_command = change_command.stderr(Stdio::piped()).spawn()?;
         let status = change_command.wait().await.map_err(|err| {
             app_handle.log(&"Change command could not be executed", LogLevel::Error);
             err
@@ -137,8 +171,7 @@ async fn update_wallpaper_internal(app_handle: AppHandle) -> Result<()> {
                 app_handle.log(&format!("Change command error:\n{buf}"), LogLevel::Error);
             }
         }
-    }
-    else {
+    } else {
         wallpaper::set_from_path(
             wallpaper_path
                 .to_str()
@@ -147,98 +180,336 @@ async fn update_wallpaper_internal(app_handle: AppHandle) -> Result<()> {
         )
         .map_err(|e| anyhow!(e.to_string()))?;
     }
+    Ok(())
+}
 
-    let now = chrono::Utc::now().naive_utc();
-    query!(
-        "---sql
-        update queue set was_set = 1, date = $1 where id = $2",
-        now,
-        wallpaper.id
-    )
-    .execute(&app_handle.db().await)
-    .await?;
+/// Moves the wallpaper into the history
+async fn mark_set(app_handle: &AppHandle, wallpaper: &Wallpaper) -> Result<()> {
+    record_set(&app_handle.db().await, &wallpaper.id).await?;
+    // The new wallpaper is now the newest history entry
+    *app_handle.state::<HistoryCursor>().lock().await = 0;
+    Ok(())
+}
 
-    app_handle.emit_all("wallpaper_updated", &wallpaper)?;
+/// Name of a wallpaper, followed by its resolution when known
+fn wallpaper_title(wallpaper: &Wallpaper) -> String {
+    let name = wallpaper.name.as_deref().unwrap_or("Untitled");
+    match wallpaper.width.zip(wallpaper.height) {
+        Some((width, height)) => format!("{name} ({width}×{height})"),
+        None => name.to_string(),
+    }
+}
 
+/// Lets the UI and tray know about the new wallpaper
+async fn announce_wallpaper(app_handle: &AppHandle, wallpaper: &Wallpaper) -> Result<()> {
+    app_handle.emit_all("wallpaper_updated", wallpaper)?;
     app_handle
         .tray_handle()
         .get_item("open_info")
-        .set_title(wallpaper.name.as_deref().unwrap_or("Untitled"))?;
+        .set_title(wallpaper_title(wallpaper))?;
+    favorites::update_tray(app_handle, &wallpaper.id).await?;
     app_handle.log(&format!("New wallpaper: {}", wallpaper.id), LogLevel::Info);
     Ok(())
 }
 
-pub fn setup_changer(app_handle: AppHandle) -> Sender<Duration> {
-    let (tx_dur, mut rx_dur) = async_runtime::channel::<Duration>(100);
-    let mut handle: Option<JoinHandle<_>> = None;
-    async_runtime::spawn((async move || loop {
-        let dur = if let Some(dur) = rx_dur.recv().await {
-            dur
-        } else {
-            break;
-        };
-        if let Some(handle) = &handle {
-            handle.abort();
+/// Outputs to set wallpapers on, each with the sources it may pick from.
+/// A single `None` output means the same wallpaper goes on every monitor
+pub async fn wallpaper_targets(app_handle: &AppHandle) -> Vec<(Option<Output>, Vec<String>)> {
+    let config = app_handle.get_config().await;
+    let outputs = if config.setter_command.is_some() {
+        outputs(app_handle).await
+    } else {
+        Vec::new()
+    };
+    if outputs.is_empty() {
+        return vec![(None, config.sources.into_keys().collect())];
+    }
+    outputs
+        .into_iter()
+        .map(|(output, display)| {
+            let sources = config
+                .sources
+                .keys()
+                .filter(|s| display.sources.is_empty() || display.sources.contains(s))
+                .cloned()
+                .collect();
+            (Some(output), sources)
+        })
+        .collect()
+}
+
+/// How many wallpapers in a row may fail their filter before giving up on a change
+const MAX_REJECTIONS: usize = 10;
+
+/// Picks a wallpaper from `sources` which passes its source's filter, along with its file.
+/// Dimensions come from the plugin where possible, and are probed from the file otherwise
+async fn pick_filtered_wallpaper(
+    app_handle: &AppHandle,
+    sources: &[&String],
+    reserved: &[String],
+) -> Result<(Wallpaper, PathBuf)> {
+    for _ in 0..MAX_REJECTIONS {
+        let wallpaper = pick_wallpaper(app_handle, sources, reserved).await?;
+        let filter = filters::filter_for(app_handle, &wallpaper.source).await;
+        // Favorites were chosen by hand, so they aren't filtered
+        if filter.is_empty() || favorites::is_favorite(app_handle, &wallpaper.id).await? {
+            let path = wallpaper_path(app_handle, &wallpaper).await?;
+            return Ok((wallpaper, path));
         }
-        if dur.is_zero() {
-            println!("Updates disabled");
+        if let Some((width, height)) = wallpaper.width.zip(wallpaper.height)
+            && let Some(reason) = filter.check(width as u32, height as u32)
+        {
+            filters::reject_wallpaper(app_handle, &wallpaper, &reason).await?;
             continue;
         }
-        let other_app_handle = app_handle.clone();
-        handle = Some(async_runtime::spawn(async move {
-            let mut interval = interval(dur);
-            interval.tick().await;
-            loop {
-                interval.tick().await;
-                update_wallpaper_internal(other_app_handle.app_handle())
-                    .await
-                    .unwrap_or_else(|err| eprintln!("{:#?}", err));
+        let path = wallpaper_path(app_handle, &wallpaper).await?;
+        if wallpaper.width.is_none() || wallpaper.height.is_none() {
+            match filters::probe_dimensions(&path) {
+                Ok((width, height)) => {
+                    filters::record_dimensions(app_handle, &wallpaper.id, width, height).await?;
+                    if let Some(reason) = filter.check(width, height) {
+                        filters::reject_wallpaper(app_handle, &wallpaper, &reason).await?;
+                        continue;
+                    }
+                }
+                // Better to show an image of unknown size than nothing at all
+                Err(err) => app_handle.log(
+                    &format!("Couldn't read the dimensions of {}: {err}", wallpaper.id),
+                    LogLevel::Info,
+                ),
             }
-        }));
-    })());
-    tx_dur
+        }
+        return Ok((wallpaper, path));
+    }
+    Err(anyhow!(
+        "{MAX_REJECTIONS} wallpapers in a row didn't pass their filters"
+    ))
 }
 
-pub async fn download_wallpaper(app_handle: &AppHandle, wp_url: impl Display) -> Result<PathBuf> {
-    let wp_res = reqwest::get(&wp_url.to_string()).await?;
-    let wallpaper_filename = format!(
-        "{}.{}",
-        hash_url(&wp_url),
-        Mime::from_str(
-            wp_res
-                .headers()
-                .get("Content-Type")
-                .ok_or(anyhow!("Couldn't determine extension"))?
-                .to_str()?
-        )?
-        .subtype()
-        .as_str()
-    );
-    let config = app_handle.get_config().await;
-    let cache_folder = config.cache_dir.clone();
-    let wallpaper_filename = cache_folder.join(wallpaper_filename);
-    // If the folder isn't full of photos
-    while read_dir_sync(&cache_folder)?
-        .filter_map(|f| Some(f.ok()?.metadata().ok()?.len()))
-        .sum::<u64>()
-        + wp_res.content_length().unwrap_or(0)
-        >= (config.cache_size * 1024.0 * 1024.0).floor() as u64
-    {
-        let oldest_download = read_dir_sync(&config.cache_dir)?
-            .find_map(|f| {
-                let f = f.ok()?;
-                if !mime_guess::from_path(f.path())
-                    .iter()
-                    .any(|g| g.type_() == IMAGE)
-                {
-                    return None;
+/// Picks the next wallpaper from `sources` along with its file, skipping `reserved` ones
+pub async fn choose_wallpaper(
+    app_handle: &AppHandle,
+    sources: &[String],
+    reserved: &[String],
+) -> Result<(Wallpaper, PathBuf)> {
+    // Favorites were picked by hand, so they aren't filtered
+    if app_handle.get_config().await.favorites_only {
+        let wallpaper = favorites::random_favorite(app_handle).await?;
+        let path = wallpaper_path(app_handle, &wallpaper).await?;
+        return Ok((wallpaper, path));
+    }
+    pick_filtered_wallpaper(app_handle, &sources.iter().collect::<Vec<_>>(), reserved).await
+}
+
+async fn update_wallpaper_internal(app_handle: AppHandle) -> Result<()> {
+    let mut first = None;
+    for (output, sources) in wallpaper_targets(&app_handle).await {
+        let (wallpaper, wallpaper_path) =
+            match take_prepared(&app_handle, output.as_ref(), &sources).await? {
+                Some(prepared) => prepared,
+                None => choose_wallpaper(&app_handle, &sources, &[]).await?,
+            };
+        let wallpaper_path =
+            process_wallpaper(&app_handle, &wallpaper, &wallpaper_path, output.as_ref()).await?;
+        apply_wallpaper(&app_handle, &wallpaper_path, output.as_ref()).await?;
+        // Each rotation is a single history entry, the first output's wallpaper
+        if first.is_none() {
+            mark_set(&app_handle, &wallpaper).await?;
+            first = Some(wallpaper);
+        } else {
+            retire(&app_handle.db().await, &wallpaper.id).await?;
+        }
+    }
+    if let Some(wallpaper) = first {
+        announce_wallpaper(&app_handle, &wallpaper).await?;
+    }
+    refresh_lookahead(app_handle);
+    Ok(())
+}
+
+/// How long the changer sleeps at most before checking the clock again,
+/// so that suspends and clock changes don't delay a change for too long
+const CLOCK_RECHECK: Duration = Duration::from_secs(60);
+
+pub fn setup_changer(app_handle: AppHandle) -> Sender<Rotation> {
+    let (tx_rotation, mut rx_rotation) = async_runtime::channel::<Rotation>(100);
+    async_runtime::spawn(async move {
+        let mut rotation: Option<Rotation> = None;
+        let mut next_fire: Option<DateTime<Local>> = None;
+        // Scheduled changes which failed in a row
+        let mut failures = 0;
+        loop {
+            let sleep_for = next_fire.map(|next| {
+                (next - Local::now())
+                    .to_std()
+                    .unwrap_or(Duration::ZERO)
+                    .min(CLOCK_RECHECK)
+            });
+            select! {
+                received = rx_rotation.recv() => {
+                    let Some(received) = received else {
+                        break;
+                    };
+                    next_fire = received.next_fire(Local::now());
+                    if next_fire.is_none() {
+                        app_handle.log(&"Updates disabled", LogLevel::Info);
+                    }
+                    rotation = Some(received);
                 }
-                Some(f.path())
-            })
-            .ok_or(anyhow!("No downloads to delete"))?;
-        fs::remove_file(oldest_download)?;
+                _ = sleep(sleep_for.unwrap_or_default()), if sleep_for.is_some() => {
+                    if next_fire.is_some_and(|next| next > Local::now()) {
+                        continue;
+                    }
+                    match app_handle.pause_state().await {
+                        PauseState::Running => {}
+                        PauseState::Paused => {
+                            next_fire = rotation
+                                .as_ref()
+                                .and_then(|rotation| rotation.next_fire(Local::now()));
+                            continue;
+                        }
+                        PauseState::PausedUntil(until) => {
+                            next_fire = Some(until.with_timezone(&Local));
+                            continue;
+                        }
+                    }
+                    let result = update_wallpaper_internal(app_handle.app_handle()).await;
+                    next_fire = rotation
+                        .as_ref()
+                        .and_then(|rotation| rotation.next_fire(Local::now()));
+                    match result {
+                        Ok(()) => failures = 0,
+                        Err(err) => {
+                            failures += 1;
+                            app_handle.log(
+                                &format!("Couldn't update the wallpaper: {err}"),
+                                LogLevel::Error,
+                            );
+                            // Try again soon rather than waiting out a whole interval,
+                            // unless it keeps failing
+                            let policy = app_handle.get_config().await.retry;
+                            if failures < policy.max_attempts
+                                && let Ok(delay) =
+                                    chrono::Duration::from_std(policy.delay(failures))
+                            {
+                                let retry_at = Local::now() + delay;
+                                next_fire = next_fire.map(|next| next.min(retry_at));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    });
+    tx_rotation
+}
+
+/// Bytes read to recognise an image's format
+const FORMAT_HEADER_SIZE: u64 = 64;
+
+/// Writes a wallpaper's data to `path`, streaming remote data rather than holding it in memory.
+/// Downloads larger than `max_size` are abandoned
+async fn fetch_to(url: Url, path: &Path, max_size: u64) -> Result<()> {
+    let too_large = || {
+        Permanent(format!(
+            "Larger than the maximum of {} MB",
+            max_size / 1024 / 1024
+        ))
+    };
+    // Local sources hand out file:// URLs, which are copied into the cache rather than fetched
+    if url.scheme() == "file" {
+        let source = url
+            .to_file_path()
+            .map_err(|_| Permanent(format!("Invalid file URL {url}")))?;
+        if tokio::fs::metadata(&source).await?.len() > max_size {
+            return Err(too_large().into());
+        }
+        // Linking saves space, but only works within the same filesystem
+        if tokio::fs::hard_link(&source, path).await.is_err() {
+            tokio::fs::copy(&source, path).await?;
+        }
+        return Ok(());
     }
-    fs::write(&wallpaper_filename, wp_res.bytes().await?)?;
+    let mut response = reqwest::get(url).await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_size)
+    {
+        return Err(too_large().into());
+    }
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut written = 0;
+    while let Some(chunk) = response.chunk().await? {
+        written += chunk.len() as u64;
+        if written > max_size {
+            return Err(too_large().into());
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// Recognises an image by its magic bytes, whatever its name or the server claims it is
+async fn detect_format(path: &Path) -> Result<ImageFormat> {
+    let mut header = Vec::new();
+    tokio::fs::File::open(path)
+        .await?
+        .take(FORMAT_HEADER_SIZE)
+        .read_to_end(&mut header)
+        .await?;
+    image::guess_format(&header).map_err(|_| Permanent("Not a supported image".to_string()).into())
+}
+
+/// Downloads a wallpaper into the cache, making room for it first.
+/// The data goes to a temporary file, which only takes its final name once it's complete and
+/// known to be an image, so an interrupted download never leaves a broken file behind
+pub async fn download_wallpaper(app_handle: &AppHandle, wallpaper: &Wallpaper) -> Result<PathBuf> {
+    let config = app_handle.get_config().await;
+    let max_size = (config.max_download_size * 1024.0 * 1024.0).floor() as u64;
+    let url = Url::parse(&wallpaper.data_url)?;
+    let partial = config
+        .cache_dir
+        .join(format!("{:08x}.{PARTIAL_EXTENSION}", rand::random::<u32>()));
+    let mut backoff = Backoff::new(app_handle, format!("Downloading {}", wallpaper.id)).await;
+    let format = loop {
+        let fetched = match fetch_to(url.clone(), &partial, max_size).await {
+            Ok(()) => detect_format(&partial).await,
+            Err(err) => Err(err),
+        };
+        match fetched {
+            Ok(format) => break format,
+            Err(err) => {
+                tokio::fs::remove_file(&partial).await.ok();
+                backoff
+                    .retry(err)
+                    .await
+                    .map_err(|err| anyhow!("Couldn't download {}: {err}", wallpaper.id))?;
+            }
+        }
+    };
+    let lock = app_handle.state::<cache::CacheLock>();
+    let _moving = lock.read().await;
+    // The content's hash names the file
+    let hash = {
+        let partial = partial.clone();
+        async_runtime::spawn_blocking(move || sha256::try_digest(partial.as_path())).await??
+    };
+    // Another wallpaper may have turned out to be the same image
+    if let Some(existing) = cache::content_file(app_handle, &hash).await? {
+        tokio::fs::remove_file(&partial).await?;
+        cache::record_content(app_handle, &wallpaper.id, &hash).await?;
+        cache::touch_file(app_handle, &existing).await?;
+        return Ok(existing);
+    }
+    let extension = format.extensions_str().first().unwrap_or(&"img");
+    let wallpaper_filename = config.cache_dir.join(format!("{hash}.{extension}"));
+    let current = current_wallpaper(app_handle).await?.map(|w| w.id);
+    let size = tokio::fs::metadata(&partial).await?.len();
+    cache::make_room(app_handle, size, current.as_deref()).await?;
+    tokio::fs::rename(&partial, &wallpaper_filename).await?;
+    cache::record_content(app_handle, &wallpaper.id, &hash).await?;
+    cache::record_file(app_handle, &hash, &wallpaper_filename, false).await?;
     Ok(wallpaper_filename)
 }
 
@@ -254,75 +525,28 @@ pub async fn update_wallpaper(app_handle: AppHandle) -> Result<()> {
     Ok(res)
 }
 
+/// Puts the wallpaper on every output, without recording it in the history
+pub async fn show_wallpaper(app_handle: &AppHandle, wallpaper: &Wallpaper) -> Result<()> {
+    let wallpaper_path = wallpaper_path(app_handle, wallpaper).await?;
+    for (output, _) in wallpaper_targets(app_handle).await {
+        let processed =
+            process_wallpaper(app_handle, wallpaper, &wallpaper_path, output.as_ref()).await?;
+        apply_wallpaper(app_handle, &processed, output.as_ref()).await?;
+    }
+    announce_wallpaper(app_handle, wallpaper).await
+}
+
 #[command]
 pub async fn set_wallpaper(app_handle: AppHandle, wallpaper: Wallpaper) -> Result<()> {
-    let wallpaper = wallpaper;
-    let cache_dir = &app_handle.get_config().await.cache_dir;
-    let cache_dir_clone = cache_dir.clone();
-    let downloaded_file = fs::read_dir(cache_dir)?.find_map(|e| {
-        if let Some(name) = (e.as_ref().ok()?.file_name().into_string()).ok()
-            && hash_url(&name) == wallpaper.id
-        {
-            Some(name)
-        } else {
-            None
-        }
-    });
-
-    let wallpaper_path = if let Some(path) = downloaded_file {
-        Ok(cache_dir_clone.join(path))
-    } else {
-        download_wallpaper(&app_handle, &wallpaper.data_url)
-            .await
-            .map(|p| cache_dir_clone.join(p))
-    }?;
-
-    wallpaper::set_from_path(
-        wallpaper_path
-            .to_str()
-            .ok_or("Invalid path")
-            .map_err(|e| anyhow!("{e:#?}"))?,
-    )
-    .map_err(|e| anyhow!(e.to_string()))?;
-
-    let now = chrono::Utc::now().naive_utc();
-    query!(
-        "---sql
-            update queue set was_set = 1, date = $1 where id = $2",
-        now,
-        wallpaper.id
-    )
-    .execute(&app_handle.db().await)
-    .await?;
-
-    app_handle
-        .tray_handle()
-        .get_item("open_info")
-        .set_title(wallpaper.name.as_deref().unwrap_or("Untitled"))?;
-    app_handle.emit_all("wallpaper_updated", wallpaper.clone())?;
-    eprintln!("New wallpaper: {:#?}", wallpaper);
+    show_wallpaper(&app_handle, &wallpaper).await?;
+    mark_set(&app_handle, &wallpaper).await?;
     Ok(())
 }
 
 #[command]
 pub async fn get_wallpaper(app_handle: AppHandle, wallpaper: Wallpaper) -> Result<String> {
-    let cache_dir = &app_handle.get_config().await.cache_dir;
-    let file = read_dir_sync(cache_dir)?.find_map(|e| {
-        if let Some(name) = (e.as_ref().ok()?.file_name().into_string()).ok()
-            && name != ""
-            && name.starts_with(&wallpaper.id)
-        {
-            Some(e.as_ref().ok()?.path())
-        } else {
-            None
-        }
-    });
-    let data = if let Some(file) = file {
-        read(file).await?
-    } else {
-        let file = download_wallpaper(&app_handle, &wallpaper.data_url).await?;
-        read(file).await?
-    };
+    let file = wallpaper_path(&app_handle, &wallpaper).await?;
+    let data = read(file).await?;
     // base64 encode data
     Ok(general_purpose::STANDARD.encode(&data))
 }
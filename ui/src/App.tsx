@@ -1,6 +1,8 @@
 import { invoke } from "@tauri-apps/api";
 import Config from "./config/Config";
 import Log from "./Log";
+import WallpaperInfo from "./WallpaperInfo";
+import Prefetch from "./Prefetch";
 import { createEffect, on } from "solid-js";
 import { appConfig } from "./context/config";
 
@@ -18,7 +20,11 @@ function App() {
       <div class="flex-initial">
         <div class="card">
           <div class="card-body">
+            <WallpaperInfo />
+            <Prefetch />
+            <button class="btn" onClick={() => invoke("previous_wallpaper")}>Previous</button>
             <button class="btn btn-primary" onClick={() => invoke("update_wallpaper")}>Update wallpaper</button>
+            <button class="btn" onClick={() => invoke("next_wallpaper")}>Next</button>
             <button class="btn btn-warning" onClick={() => invoke("exit")}>Quit</button>
           </div>
         </div>
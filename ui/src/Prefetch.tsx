@@ -0,0 +1,21 @@
+import { invoke } from "@tauri-apps/api";
+import { listen } from "@tauri-apps/api/event"
+import { PrefetchProgress } from "$rs/PrefetchProgress";
+import { Show, createSignal } from "solid-js";
+import { formatSize } from "./WallpaperInfo";
+
+export default () => {
+    const [progress, setProgress] = createSignal<PrefetchProgress>();
+    listen("prefetch_progress", e => setProgress(e.payload as PrefetchProgress));
+    return <Show when={progress()?.running && progress()}>{progress =>
+        <div class="flex items-center gap-2 text-sm">
+            <span>Prefetching {progress().done}/{progress().total}</span>
+            <progress class="progress flex-auto" value={progress().done} max={progress().total} />
+            <span>{formatSize(progress().bytes)}</span>
+            <Show when={progress().failed}>
+                <span class="text-error">{progress().failed} failed</span>
+            </Show>
+            <button class="btn btn-xs" onClick={() => invoke("cancel_prefetch")}>Cancel</button>
+        </div>
+    }</Show>
+}
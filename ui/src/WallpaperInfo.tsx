@@ -0,0 +1,34 @@
+import { listen } from "@tauri-apps/api/event"
+import { Wallpaper } from "$rs/Wallpaper";
+import { For, Show, createSignal } from "solid-js";
+
+export const formatSize = (bytes: number) => {
+    const units = ["B", "KiB", "MiB", "GiB"];
+    let unit = 0;
+    while (bytes >= 1024 && unit < units.length - 1) {
+        bytes /= 1024;
+        unit++;
+    }
+    return `${bytes.toFixed(unit ? 1 : 0)} ${units[unit]}`;
+}
+
+export default () => {
+    const [wallpaper, setWallpaper] = createSignal<Wallpaper>();
+    listen("wallpaper_updated", e => setWallpaper(e.payload as Wallpaper));
+    return <Show when={wallpaper()}>{wallpaper =>
+        <div class="flex flex-wrap items-center gap-2 text-sm">
+            <span class="font-bold">{wallpaper().name ?? "Untitled"}</span>
+            <Show when={wallpaper().width && wallpaper().height}>
+                <span>{wallpaper().width}×{wallpaper().height}</span>
+            </Show>
+            <Show when={wallpaper().file_size}>{size => <span>{formatSize(size())}</span>}</Show>
+            <Show when={wallpaper().author}>{author => <span>by {author()}</span>}</Show>
+            <For each={wallpaper().colors?.split(",") ?? []}>{color =>
+                <span class="w-4 h-4 rounded" style={{ "background-color": color }} title={color} />
+            }</For>
+            <For each={wallpaper().tags?.split(",") ?? []}>{tag =>
+                <span class="badge badge-outline">{tag}</span>
+            }</For>
+        </div>
+    }</Show>
+}
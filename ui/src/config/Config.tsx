@@ -11,7 +11,7 @@ import DurationEditor from "./DurationEditor";
 import { ConfigUpdate } from "$rs/ConfigUpdate";
 // import { For } from "solid-js";
 
-const update = <Prop extends Exclude<keyof AppConfig, "interval" | "sources">, EventValue,
+const update = <Prop extends Exclude<keyof AppConfig, "interval" | "schedule" | "sources">, EventValue,
     Transformer extends ((v: EventValue) => AppConfig[Prop]) | undefined = undefined>
     (prop: Prop, transformer?: Transformer) => {
     return debounce((event: ({ target: { value: undefined extends Transformer ? AppConfig[Prop] : EventValue } }) | AppConfig[Prop]) => {
@@ -31,6 +31,8 @@ export default () => {
     return <div class="card">
         <form class="card-body" onSubmit={e => { e.preventDefault() }}>
             <Range label="Cache size" value={appConfig().cache_size} max={10000} min={10} unit="MB" onInput={update("cache_size")} />
+            <Range label="Maximum download size" value={appConfig().max_download_size} max={500} min={1} unit="MB" onInput={update("max_download_size")} />
+            <Range label="Parallel downloads" value={appConfig().prefetch_concurrency} max={16} min={1} onInput={update("prefetch_concurrency")} />
             <div class="join w-full pr-12">
                 <label class="join-item w-full">
                     Change interval:
@@ -51,6 +53,14 @@ export default () => {
                         onInput={e => update("display_background")(e.target.checked)} />
                 </label>
             </div>
+            <div class="join">
+                <label class="join-item">
+                    Favorites only
+                    <input type="checkbox" class="checkbox mx-2 join-item"
+                        checked={appConfig().favorites_only}
+                        onInput={e => update("favorites_only")(e.target.checked)} />
+                </label>
+            </div>
             <div class="join">
                 <label class="join-item">
                     Plugins directory
@@ -9,16 +9,29 @@ const [appConfig, { mutate }] = createResource<AppConfig>(async () => await invo
         history_amount: 0,
         cache_dir: "",
         cache_size: 0,
+        max_download_size: 0,
+        prefetch_concurrency: 1,
+        retry: { max_attempts: 1, base_delay_ms: 0, max_delay_ms: 0 },
         sources: [],
+        source_weights: {},
+        source_selection: "Weighted",
         display_background: false,
         interval: {
             nanos: 0,
             secs: 10
         },
+        schedule: [],
         theme: "default",
         plugin_host_mode: "Daemon",
         plugins_dir: null,
         setter_command: null,
+        favorites_only: false,
+        displays: {},
+        filter: { min_width: null, min_height: null, aspect_ratios: [] },
+        source_filters: {},
+        scaling: "Original",
+        background_color: null,
+        effects: [],
         logging: [{ UIToast: "Info" }],
     }
 });